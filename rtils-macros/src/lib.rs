@@ -0,0 +1,226 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    FnArg, Ident, ImplItem, ImplItemFn, ItemImpl, Pat, ReturnType, Type, Visibility,
+    parse_macro_input, spanned::Spanned,
+};
+
+struct Method {
+    name: Ident,
+    args: Vec<(String, Type)>,
+    returns: Option<Type>,
+}
+
+/*
+* the tokens of a type with the spaces quote puts everywhere taken back out, so the schema reads
+* Vec<String> and not Vec < String >
+* */
+fn type_name(ty: &Type) -> String {
+    let mut out = String::new();
+    let text = quote!(#ty).to_string();
+    let mut last = ' ';
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ' ' {
+            let next = chars.peek().copied().unwrap_or(' ');
+            let word = |x: char| x.is_alphanumeric() || x == '_' || x == '\'';
+            let keep = (word(last) && word(next)) || last == ',' || last == ';';
+            if !keep {
+                continue;
+            }
+        }
+        out.push(c);
+        last = c;
+    }
+    out
+}
+
+fn method(item: &ImplItemFn) -> syn::Result<Option<Method>> {
+    let sig = &item.sig;
+    if !matches!(item.vis, Visibility::Public(_))
+        || !matches!(sig.inputs.first(), Some(FnArg::Receiver(r)) if r.reference.is_some())
+    {
+        return Ok(None);
+    }
+    if sig.asyncness.is_some() {
+        return Err(syn::Error::new(
+            sig.span(),
+            "object methods run inside the event loop and can't be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "object methods can't be generic",
+        ));
+    }
+    let mut args = Vec::new();
+    for arg in sig.inputs.iter().skip(1) {
+        let FnArg::Typed(arg) = arg else {
+            continue;
+        };
+        let name = match arg.pat.as_ref() {
+            Pat::Ident(x) => x.ident.to_string(),
+            _ => format!("arg{}", args.len()),
+        };
+        args.push((name, (*arg.ty).clone()));
+    }
+    let returns = match &sig.output {
+        ReturnType::Default => None,
+        ReturnType::Type(_, ty) => Some((**ty).clone()),
+    };
+    Ok(Some(Method {
+        name: sig.ident.clone(),
+        args,
+        returns,
+    }))
+}
+
+fn expand(item: ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
+    if !item.generics.params.is_empty() || item.trait_.is_some() {
+        return Err(syn::Error::new(
+            item.span(),
+            "#[object] goes on a plain impl block of a non generic type",
+        ));
+    }
+    let Type::Path(self_path) = item.self_ty.as_ref() else {
+        return Err(syn::Error::new(item.self_ty.span(), "expected a type name"));
+    };
+    let self_ty = &item.self_ty;
+    let type_ident = &self_path.path.segments.last().unwrap().ident;
+    let client = format_ident!("{}Client", type_ident);
+    let mut methods = Vec::new();
+    for x in &item.items {
+        if let ImplItem::Fn(f) = x
+            && let Some(m) = method(f)?
+        {
+            methods.push(m);
+        }
+    }
+
+    let names: Vec<String> = methods.iter().map(|m| m.name.to_string()).collect();
+    let arms = methods.iter().zip(&names).map(|(m, name)| {
+        let ident = &m.name;
+        let tys: Vec<&Type> = m.args.iter().map(|x| &x.1).collect();
+        let vars: Vec<Ident> = (0..tys.len())
+            .map(|i| Ident::new(&format!("arg{}", i), Span::call_site()))
+            .collect();
+        quote! {
+            #name => {
+                let (#(#vars,)*): (#(#tys,)*) = ::rtils::msg::decode_args(&message)?;
+                let out = self.#ident(#(#vars),*);
+                message.encoding.encode(&out)
+            }
+        }
+    });
+    let schemas = methods.iter().zip(&names).map(|(m, name)| {
+        let arg_names = m.args.iter().map(|x| &x.0);
+        let arg_tys = m.args.iter().map(|x| type_name(&x.1));
+        let returns = m
+            .returns
+            .as_ref()
+            .map(type_name)
+            .unwrap_or_else(|| "()".into());
+        quote! {
+            ::rtils::msg::MethodSchema {
+                name: #name.into(),
+                args: vec![#(::rtils::msg::ArgSchema { name: #arg_names.into(), ty: #arg_tys.into() }),*],
+                returns: #returns.into(),
+            }
+        }
+    });
+    let stubs = methods.iter().zip(&names).map(|(m, name)| {
+        let ident = &m.name;
+        let vars: Vec<Ident> = (0..m.args.len())
+            .map(|i| Ident::new(&format!("arg{}", i), Span::call_site()))
+            .collect();
+        let tys = m.args.iter().map(|x| &x.1);
+        let returns = match &m.returns {
+            Some(ty) => quote!(#ty),
+            None => quote!(()),
+        };
+        let args = if vars.is_empty() {
+            quote!(&[(); 0])
+        } else {
+            quote!(&(#(&#vars,)*))
+        };
+        quote! {
+            pub async fn #ident(&self, #(#vars: #tys),*) -> ::rtils::Throws<#returns> {
+                let msg = ::rtils::msg::Message::new(self.id, #name, #args)?;
+                self.events.call_object(msg).await
+            }
+        }
+    });
+    let accepts = if names.is_empty() {
+        quote!(false)
+    } else {
+        quote!(matches!(message.to_call.as_ref(), #(#names)|*))
+    };
+    let type_name = type_ident.to_string();
+    let client_doc = format!(
+        " Calls {} through the event bus, one async fn per method.",
+        type_name
+    );
+
+    Ok(quote! {
+        #item
+
+        impl ::rtils::msg::Object for #self_ty {
+            fn call(&mut self, message: ::rtils::msg::Message) -> ::rtils::Throws<Vec<u8>> {
+                match message.to_call.as_ref() {
+                    #(#arms)*
+                    _ => Err(::rtils::msg::CallError::UnknownMethod {
+                        method: message.to_call.clone(),
+                    }
+                    .into()),
+                }
+            }
+
+            fn can_accept(&self, message: ::rtils::msg::Message) -> bool {
+                #accepts
+            }
+
+            fn describe(&self) -> ::rtils::msg::ObjectSchema {
+                ::rtils::msg::ObjectSchema {
+                    name: #type_name.into(),
+                    methods: vec![#(#schemas),*],
+                }
+            }
+        }
+
+        #[doc = #client_doc]
+        pub struct #client<T: ::rtils::events::ThreadSafeIsh> {
+            pub id: ::rtils::msg::ObjectId,
+            pub events: ::rtils::events::EventSync<T>,
+        }
+
+        impl<T: ::rtils::events::ThreadSafeIsh> #client<T> {
+            pub fn new(id: ::rtils::msg::ObjectId, events: ::rtils::events::EventSync<T>) -> Self {
+                Self { id, events }
+            }
+
+            #(#stubs)*
+        }
+    })
+}
+
+/*
+* put on the impl block of a type to make it an Object. every pub method taking &self or &mut self
+* can be called through a Message, Object::describe lists them with their argument names and
+* types, and a TypeClient struct gets an async fn per method that sends the call over an EventSync
+* and waits for the result
+* */
+#[proc_macro_attribute]
+pub fn object(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(Span::call_site(), "#[object] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as ItemImpl);
+    match expand(item) {
+        Ok(x) => x.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
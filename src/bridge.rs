@@ -0,0 +1,526 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::{
+    Exception, Throws,
+    events::{BPipe, Daemon, Event, EventForwarder, EventSync, Origin, RemoteEvent, ThreadSafeIsh},
+    throw,
+};
+
+const BRIDGE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*
+* the same framing as the blocking stream_write_bytes, a little endian u64 length and then the
+* bytes
+* */
+pub async fn stream_write_bytes<W: AsyncWrite + Unpin>(stream: &mut W, bytes: &[u8]) -> Throws<()> {
+    stream
+        .write_all(&(bytes.len() as u64).to_le_bytes())
+        .await?;
+    stream.write_all(bytes).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/*
+* frames longer than max are refused before anything is allocated for them
+* */
+pub async fn stream_read_bytes<R: AsyncRead + Unpin>(stream: &mut R, max: u64) -> Throws<Vec<u8>> {
+    let mut len = [0; 8];
+    stream.read_exact(&mut len).await?;
+    let len = u64::from_le_bytes(len);
+    if len > max {
+        throw!(format!(
+            "frame of {} bytes is over the limit of {}",
+            len, max
+        ));
+    }
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum BridgeFrame<T> {
+    Hello {
+        node: u64,
+    },
+    Event {
+        origin: Origin,
+        event: RemoteEvent<T>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    pub forward_messages: bool,
+    pub reconnect_min: Duration,
+    pub reconnect_max: Duration,
+    pub max_frame: u64,
+    pub backlog: usize,
+}
+
+pub struct BridgeConfigBuilder {
+    forward_messages: bool,
+    reconnect_min: Duration,
+    reconnect_max: Duration,
+    max_frame: u64,
+    backlog: usize,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl BridgeConfig {
+    pub fn builder() -> BridgeConfigBuilder {
+        BridgeConfigBuilder {
+            forward_messages: true,
+            reconnect_min: Duration::from_millis(100),
+            reconnect_max: Duration::from_secs(10),
+            max_frame: 16 * 1024 * 1024,
+            backlog: 1024,
+        }
+    }
+}
+
+impl BridgeConfigBuilder {
+    pub fn build(self) -> BridgeConfig {
+        BridgeConfig {
+            forward_messages: self.forward_messages,
+            reconnect_min: self.reconnect_min,
+            reconnect_max: self.reconnect_max.max(self.reconnect_min),
+            max_frame: self.max_frame,
+            backlog: self.backlog,
+        }
+    }
+    /*
+     * send Messages for objects owned by other nodes across, and the replies to them back, as
+     * well as the selected UserDefined events. messages to ids with node 0 never leave the
+     * handler they were sent on
+     * */
+    pub fn forward_messages(mut self, enabled: bool) -> Self {
+        self.forward_messages = enabled;
+        self
+    }
+    /*
+     * the wait after a failed connect or a dropped session starts at min and doubles up to max,
+     * it goes back to min once a session has carried an event either way
+     * */
+    pub fn reconnect_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.reconnect_min = min;
+        self.reconnect_max = max;
+        self
+    }
+    pub fn max_frame(mut self, bytes: u64) -> Self {
+        self.max_frame = bytes;
+        self
+    }
+    /*
+     * how many events are held while there is no peer, the oldest are dropped past this
+     * */
+    pub fn backlog(mut self, events: usize) -> Self {
+        self.backlog = events;
+        self
+    }
+}
+
+/*
+* the reading half of a session, aborted when the session ends or the bridge is killed in the
+* middle of one
+* */
+struct Incoming(JoinHandle<Throws<()>>);
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+enum BridgeEndpoint {
+    Connect(String),
+    Listen(TcpListener),
+}
+
+/*
+* a Daemon that links this handler with one other over tcp. selected UserDefined events,
+* Messages for objects on other nodes and the replies to them go out and what the peer sends
+* comes back in as Event::Remote, each keeping the origin it was first sent with so neither side
+* sends an event back to where it came from
+* */
+pub struct TcpBridge<T: ThreadSafeIsh> {
+    endpoint: BridgeEndpoint,
+    config: BridgeConfig,
+    events: EventSync<T>,
+    outgoing: BPipe<Event<T>>,
+    backlog: VecDeque<(Origin, RemoteEvent<T>)>,
+}
+
+#[async_trait]
+impl<T: ThreadSafeIsh + Clone + Serialize + DeserializeOwned> Daemon for TcpBridge<T> {
+    async fn run(&mut self) {
+        let mut backoff = self.config.reconnect_min;
+        loop {
+            let traffic = Arc::new(AtomicBool::new(false));
+            let out = match self.open().await {
+                Ok(stream) => self.serve(stream, traffic.clone()).await,
+                Err(e) => Err(e),
+            };
+            if traffic.load(Ordering::Relaxed) {
+                backoff = self.config.reconnect_min;
+            }
+            if let Err(e) = out {
+                _ = self.events.report_fault(e);
+                self.wait(backoff).await;
+                backoff = (backoff * 2).min(self.config.reconnect_max);
+            }
+            if self.outgoing.is_closed() {
+                return;
+            }
+        }
+    }
+}
+
+impl<T: ThreadSafeIsh + Clone + Serialize + DeserializeOwned> TcpBridge<T> {
+    /*
+     * dials addr and keeps redialing whenever the connection is lost
+     * */
+    pub async fn connect(
+        addr: &str,
+        config: BridgeConfig,
+        should_forward: impl Fn(&T) -> bool + Send + Sync + 'static,
+        events: EventSync<T>,
+    ) -> Self {
+        Self::new(
+            BridgeEndpoint::Connect(addr.into()),
+            config,
+            should_forward,
+            events,
+        )
+        .await
+    }
+
+    /*
+     * waits for a peer on addr, one at a time, and goes back to accepting when it leaves
+     * */
+    pub async fn listen(
+        addr: &str,
+        config: BridgeConfig,
+        should_forward: impl Fn(&T) -> bool + Send + Sync + 'static,
+        events: EventSync<T>,
+    ) -> Throws<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self::new(
+            BridgeEndpoint::Listen(listener),
+            config,
+            should_forward,
+            events,
+        )
+        .await)
+    }
+
+    async fn new(
+        endpoint: BridgeEndpoint,
+        config: BridgeConfig,
+        should_forward: impl Fn(&T) -> bool + Send + Sync + 'static,
+        events: EventSync<T>,
+    ) -> Self {
+        let messages = config.forward_messages;
+        let local = events.node_id();
+        let elsewhere = move |node: u64| messages && node != 0 && node != local;
+        let outgoing = EventForwarder::new_globals(
+            move |ev| match ev {
+                Event::UserDefined(x)
+                | Event::Remote {
+                    event: RemoteEvent::User(x),
+                    ..
+                } => should_forward(x),
+                Event::Message(m)
+                | Event::Remote {
+                    event: RemoteEvent::Message(m),
+                    ..
+                } => elsewhere(m.target_id.node()),
+                Event::MessageReply { to, .. }
+                | Event::Remote {
+                    event: RemoteEvent::Reply { to, .. },
+                    ..
+                } => elsewhere(*to),
+                _ => false,
+            },
+            events.clone(),
+        )
+        .await;
+        Self {
+            endpoint,
+            config,
+            events,
+            outgoing,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    pub fn local_addr(&self) -> Throws<std::net::SocketAddr> {
+        match &self.endpoint {
+            BridgeEndpoint::Listen(listener) => Ok(listener.local_addr()?),
+            BridgeEndpoint::Connect(_) => throw!("only a listening bridge has a local address"),
+        }
+    }
+
+    /*
+     * local events get a fresh origin as they are picked up, remote ones keep theirs
+     * */
+    fn drain_local(&mut self) -> Throws<()> {
+        while let Some(ev) = self.outgoing.recieve()? {
+            let item = match ev {
+                Event::UserDefined(x) => (self.events.next_origin(), RemoteEvent::User(x)),
+                Event::Message(m) => (self.events.next_origin(), RemoteEvent::Message(m)),
+                Event::MessageReply { id, to, result } => (
+                    self.events.next_origin(),
+                    RemoteEvent::Reply { id, to, result },
+                ),
+                Event::Remote { origin, event } => (origin, event),
+                _ => continue,
+            };
+            self.backlog.push_back(item);
+            if self.backlog.len() > self.config.backlog {
+                self.backlog.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * sleeps without letting local events pile up in the pipe
+     * */
+    async fn wait(&mut self, time: Duration) {
+        let end = tokio::time::Instant::now() + time;
+        while tokio::time::Instant::now() < end {
+            _ = self.drain_local();
+            tokio::time::sleep(BRIDGE_POLL_INTERVAL.min(time)).await;
+        }
+    }
+
+    async fn open(&mut self) -> Throws<TcpStream> {
+        loop {
+            let accepted = match &self.endpoint {
+                BridgeEndpoint::Connect(addr) => return Ok(TcpStream::connect(addr).await?),
+                BridgeEndpoint::Listen(listener) => {
+                    tokio::time::timeout(BRIDGE_POLL_INTERVAL, listener.accept()).await
+                }
+            };
+            match accepted {
+                Ok(con) => return Ok(con?.0),
+                Err(_) => self.drain_local()?,
+            }
+        }
+    }
+
+    /*
+     * one session with a peer, traffic is set once an event has gone either way
+     * */
+    async fn serve(&mut self, stream: TcpStream, traffic: Arc<AtomicBool>) -> Throws<()> {
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+        let hello: BridgeFrame<T> = BridgeFrame::Hello {
+            node: self.events.node_id(),
+        };
+        stream_write_bytes(&mut writer, &rmp_serde::to_vec(&hello)?).await?;
+        let frame = tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            stream_read_bytes(&mut reader, self.config.max_frame),
+        )
+        .await??;
+        let BridgeFrame::Hello { node: peer } = rmp_serde::from_slice::<BridgeFrame<T>>(&frame)?
+        else {
+            throw!("peer did not start with a hello");
+        };
+        if peer == self.events.node_id() {
+            throw!("bridge is connected to its own handler");
+        }
+        let events = self.events.clone();
+        let max_frame = self.config.max_frame;
+        let received = traffic.clone();
+        let mut incoming = Incoming(tokio::spawn(async move {
+            loop {
+                let frame = stream_read_bytes(&mut reader, max_frame).await?;
+                if let BridgeFrame::Event { origin, event } = rmp_serde::from_slice(&frame)? {
+                    received.store(true, Ordering::Relaxed);
+                    events.send_global(Event::Remote { origin, event }).await?;
+                }
+            }
+        }));
+        self.send_loop(&mut writer, peer, &mut incoming.0, &traffic)
+            .await
+    }
+
+    /*
+     * runs until the peer goes away, an event is only dropped from the backlog once it has been
+     * written. whatever ended the reading half is what this returns
+     * */
+    async fn send_loop(
+        &mut self,
+        writer: &mut (impl AsyncWrite + Unpin),
+        peer: u64,
+        incoming: &mut JoinHandle<Throws<()>>,
+        traffic: &AtomicBool,
+    ) -> Throws<()> {
+        loop {
+            self.drain_local()?;
+            while let Some((origin, event)) = self.backlog.front() {
+                if origin.node != peer {
+                    let frame = rmp_serde::to_vec(&BridgeFrame::Event {
+                        origin: *origin,
+                        event: event.clone(),
+                    })?;
+                    stream_write_bytes(writer, &frame).await?;
+                    traffic.store(true, Ordering::Relaxed);
+                }
+                self.backlog.pop_front();
+            }
+            if incoming.is_finished() {
+                return incoming.await?;
+            }
+            tokio::time::sleep(BRIDGE_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn tcp_bridge_tests() {
+    use crate::events::{DaemonId, EventHandler, EventRequest, EventSub, SubId};
+    use std::sync::{Arc, Mutex};
+    struct Recorder {
+        seen: Arc<Mutex<Vec<u32>>>,
+    }
+    #[async_trait]
+    impl EventSub<u32> for Recorder {
+        async fn on_create(&mut self, _: SubId, _: EventSync<u32>) {}
+        async fn wants_event(&self, _: &u32) -> Throws<EventRequest> {
+            Ok(EventRequest::Shared)
+        }
+        async fn on_event(&mut self, event: &u32) -> Throws<()> {
+            self.seen.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+    fn node() -> (EventSync<u32>, Arc<Mutex<Vec<u32>>>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (sender, mut handler) = EventHandler::<u32>::new();
+        let sync = EventSync::new(sender);
+        sync.create_new_subscriber(Recorder { seen: seen.clone() })
+            .unwrap();
+        tokio::spawn(async move {
+            let _ = handler.run(async |_| {}).await;
+        });
+        (sync, seen)
+    }
+    async fn wait_for(seen: &Arc<Mutex<Vec<u32>>>, want: &[u32]) {
+        for _ in 0..200 {
+            let mut got = seen.lock().unwrap().clone();
+            got.sort();
+            if got == want {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("saw {:?} instead of {:?}", seen.lock().unwrap(), want);
+    }
+    let config = BridgeConfig::builder()
+        .reconnect_backoff(Duration::from_millis(10), Duration::from_millis(40))
+        .build();
+    let (a, a_seen) = node();
+    let (b, b_seen) = node();
+    let bridge = TcpBridge::listen("127.0.0.1:0", config.clone(), |x| *x < 100, a.clone())
+        .await
+        .unwrap();
+    let addr = bridge.local_addr().unwrap().to_string();
+
+    let connector = TcpBridge::connect(&addr, config.clone(), |x| *x < 100, b.clone()).await;
+    b.create_daemon(Box::new(connector), DaemonId::alloc())
+        .unwrap();
+    b.new_event(2).unwrap();
+    let listener = DaemonId::alloc();
+    a.create_daemon(Box::new(bridge), listener).unwrap();
+    a.new_event(1).unwrap();
+    a.new_event(500).unwrap();
+    wait_for(&a_seen, &[1, 2, 500]).await;
+    wait_for(&b_seen, &[1, 2]).await;
+
+    a.kill_daemon(listener).unwrap();
+    let mut bridge = None;
+    for _ in 0..200 {
+        if let Ok(x) = TcpBridge::listen(&addr, config.clone(), |x| *x < 100, a.clone()).await {
+            bridge = Some(x);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    a.create_daemon(Box::new(bridge.unwrap()), DaemonId::alloc())
+        .unwrap();
+    a.new_event(3).unwrap();
+    wait_for(&a_seen, &[1, 2, 3, 500]).await;
+    wait_for(&b_seen, &[1, 2, 3]).await;
+}
+
+#[tokio::test]
+async fn bridged_call_tests() {
+    use crate::events::{DaemonId, EventHandler};
+    use crate::msg::{CallError, Message, TestObject};
+    fn node() -> EventSync<u32> {
+        let (sender, mut handler) = EventHandler::<u32>::new();
+        tokio::spawn(async move {
+            let _ = handler.run(async |_| {}).await;
+        });
+        EventSync::new(sender)
+    }
+    let config = BridgeConfig::builder()
+        .reconnect_backoff(Duration::from_millis(10), Duration::from_millis(40))
+        .build();
+    let (a, b) = (node(), node());
+    let bridge = TcpBridge::listen("127.0.0.1:0", config.clone(), |_| false, a.clone())
+        .await
+        .unwrap();
+    let addr = bridge.local_addr().unwrap().to_string();
+    a.create_daemon(Box::new(bridge), DaemonId::alloc())
+        .unwrap();
+    let bridge = TcpBridge::connect(&addr, config, |_| false, b.clone()).await;
+    b.create_daemon(Box::new(bridge), DaemonId::alloc())
+        .unwrap();
+
+    let id = b.spawn_object(Box::new(TestObject {})).unwrap();
+    assert_eq!(id.node(), b.node_id());
+    let sum = a.call_object_timeout::<i32>(
+        Message::new(id, "add", &(2, 3)).unwrap(),
+        Duration::from_secs(5),
+    );
+    assert_eq!(sum.await.unwrap(), 5);
+    b.free_object(id).unwrap();
+    let err = a
+        .call_object_timeout::<i32>(
+            Message::new(id, "add", &(2, 3)).unwrap(),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        *err.error_as::<CallError>().unwrap(),
+        CallError::NoSuchObject { id }
+    );
+}
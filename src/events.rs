@@ -1,15 +1,22 @@
-use crate::msg::{Message, Object, ObjectId};
+use crate::msg::{CallError, Message, MessageId, Object, ObjectId};
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
 use std::error::Error;
 use std::net::TcpStream;
-use std::sync::atomic::AtomicBool;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::time::Instant;
 
 #[allow(unused)]
-use crate::{Exception, Throw, Throws, server::HTTPRequest, server::HTTPResponse};
+use crate::{
+    Exception, Throw, Throws, server::HTTPRequest, server::HTTPResponse,
+    server::websocket::WsMessage, throw,
+};
 
 #[macro_export]
 macro_rules! DEFINE_ID_WRAPPER {
@@ -75,7 +82,7 @@ pub enum MouseInput {
 }
 
 #[allow(unused)]
-#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct SubId {
     inner: u64,
 }
@@ -92,7 +99,7 @@ impl SubId {
 }
 
 #[allow(unused)]
-#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct ServiceId {
     inner: u64,
 }
@@ -107,14 +114,82 @@ impl ServiceId {
         self.inner
     }
 }
-DEFINE_ID_WRAPPER!(TcpConnectionId);
 DEFINE_ID_WRAPPER!(DaemonId);
+
+/*
+* events for a connection are matched by this id alone, so it comes from a counter and is never
+* reused, otherwise a WebSocketSend meant for a closed connection could reach a newer one
+* */
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TcpConnectionId {
+    inner: u64,
+}
+impl TcpConnectionId {
+    pub fn invalid() -> Self {
+        Self { inner: 0 }
+    }
+    pub fn inner(&self) -> u64 {
+        self.inner
+    }
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self {
+            inner: NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+}
+
+/*
+* ties a forwarded HTTPRequest to its HTTPResponse, these are never reused so an answer that
+* shows up after its request timed out can't land on a newer one
+* */
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct HttpRequestId {
+    inner: u64,
+}
+impl HttpRequestId {
+    pub fn invalid() -> Self {
+        Self { inner: 0 }
+    }
+    pub fn inner(&self) -> u64 {
+        self.inner
+    }
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self {
+            inner: NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+}
+/*
+* handed out by the EventSync::schedule_* calls before the handler has seen the timer, so it comes
+* from a counter rather than the handler
+* */
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TimerId {
+    inner: u64,
+}
+impl TimerId {
+    pub fn invalid() -> Self {
+        Self { inner: 0 }
+    }
+    pub fn inner(&self) -> u64 {
+        self.inner
+    }
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self {
+            inner: NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+}
+
 pub trait ThreadSafeIsh: Send + Sync + 'static {}
 impl<T: Send + Sync + 'static> ThreadSafeIsh for T {}
 
 pub trait ThreadSafeIshErr: Error + Send + Sync + 'static {}
 impl<T: Error + Send + Sync + 'static> ThreadSafeIshErr for T {}
-#[derive(PartialEq, Clone, Hash, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug, Serialize, Deserialize)]
 pub enum EventType {
     CreateSubscriber,
     DestroySubscriber,
@@ -140,6 +215,21 @@ pub enum EventType {
     Message,
     AllocObject,
     FreeObject,
+    WebSocketOpen,
+    WebSocketMessage,
+    WebSocketSend,
+    CreateRestartableSubscriber,
+    CreateRestartableService,
+    SubscriberFaulted,
+    DaemonFaulted,
+    WakeService,
+    Request,
+    ScheduleTimer,
+    CancelTimer,
+    Shutdown,
+    Remote,
+    MessageReply,
+    DeadLetter,
 }
 
 pub enum Event<T: ThreadSafeIsh> {
@@ -173,10 +263,11 @@ pub enum Event<T: ThreadSafeIsh> {
     },
     HttpRequest {
         id: TcpConnectionId,
+        request_id: HttpRequestId,
         request: HTTPRequest,
     },
     HttpResponse {
-        id: TcpConnectionId,
+        request_id: HttpRequestId,
         response: HTTPResponse,
     },
     NetOutput {
@@ -214,6 +305,44 @@ pub enum Event<T: ThreadSafeIsh> {
     FreeObject {
         id: ObjectId,
     },
+    WebSocketOpen {
+        id: TcpConnectionId,
+        request: HTTPRequest,
+    },
+    WebSocketMessage {
+        id: TcpConnectionId,
+        message: WsMessage,
+    },
+    WebSocketSend {
+        id: TcpConnectionId,
+        message: WsMessage,
+    },
+    CreateRestartableSubscriber(SubscriberFactory<T>),
+    CreateRestartableService(ServiceFactory<T>),
+    SubscriberFaulted(FaultReport),
+    DaemonFaulted(Exception),
+    WakeService {
+        id: ServiceId,
+    },
+    Request {
+        event: T,
+        reply: Reply,
+    },
+    ScheduleTimer(Timer<T>),
+    CancelTimer {
+        id: TimerId,
+    },
+    Shutdown,
+    Remote {
+        origin: Origin,
+        event: RemoteEvent<T>,
+    },
+    MessageReply {
+        id: MessageId,
+        to: u64,
+        result: Result<Vec<u8>, CallError>,
+    },
+    DeadLetter(Message),
 }
 impl<T: Clone + ThreadSafeIsh> Event<T> {
     pub fn try_clone(&self) -> Option<Event<T>> {
@@ -231,12 +360,20 @@ impl<T: Clone + ThreadSafeIsh> Event<T> {
             Event::TcpConnection { stream: _, id: _ } => None,
             Event::NotifyNewTcpConnection { id } => Some(Event::NotifyNewTcpConnection { id: *id }),
             Event::TcpDisconnect { id } => Some(Event::TcpDisconnect { id: *id }),
-            Event::HttpRequest { id, request } => Some(Event::HttpRequest {
+            Event::HttpRequest {
+                id,
+                request_id,
+                request,
+            } => Some(Event::HttpRequest {
                 id: *id,
+                request_id: *request_id,
                 request: request.clone(),
             }),
-            Event::HttpResponse { id, response } => Some(Event::HttpResponse {
-                id: *id,
+            Event::HttpResponse {
+                request_id,
+                response,
+            } => Some(Event::HttpResponse {
+                request_id: *request_id,
                 response: response.clone(),
             }),
             Event::NetOutput { id, data } => Some(Event::NetOutput {
@@ -258,6 +395,37 @@ impl<T: Clone + ThreadSafeIsh> Event<T> {
             Event::Message(message) => Some(Event::Message(message.clone())),
             Event::AllocObject { id: _, object: _ } => None,
             Event::FreeObject { id: _ } => None,
+            Event::WebSocketOpen { id, request } => Some(Event::WebSocketOpen {
+                id: *id,
+                request: request.clone(),
+            }),
+            Event::WebSocketMessage { id, message } => Some(Event::WebSocketMessage {
+                id: *id,
+                message: message.clone(),
+            }),
+            Event::WebSocketSend { id, message } => Some(Event::WebSocketSend {
+                id: *id,
+                message: message.clone(),
+            }),
+            Event::CreateRestartableSubscriber(_) => None,
+            Event::CreateRestartableService(_) => None,
+            Event::SubscriberFaulted(report) => Some(Event::SubscriberFaulted(report.clone())),
+            Event::DaemonFaulted(_) => None,
+            Event::WakeService { id } => Some(Event::WakeService { id: *id }),
+            Event::Request { .. } => None,
+            Event::ScheduleTimer(_) => None,
+            Event::CancelTimer { id } => Some(Event::CancelTimer { id: *id }),
+            Event::Shutdown => Some(Event::Shutdown),
+            Event::Remote { origin, event } => Some(Event::Remote {
+                origin: *origin,
+                event: event.clone(),
+            }),
+            Event::MessageReply { id, to, result } => Some(Event::MessageReply {
+                id: *id,
+                to: *to,
+                result: result.clone(),
+            }),
+            Event::DeadLetter(message) => Some(Event::DeadLetter(message.clone())),
         }
     }
 
@@ -278,8 +446,8 @@ impl<T: Clone + ThreadSafeIsh> Event<T> {
             Event::TcpDisconnect { id: _ } => true,
             Event::NetInput { id: _, data: _ } => true,
             Event::NetOutput { id: _, data: _ } => true,
-            Event::HttpRequest { id: _, request: _ } => true,
-            Event::HttpResponse { id: _, response: _ } => true,
+            Event::HttpRequest { .. } => true,
+            Event::HttpResponse { .. } => true,
             Event::RequestConnectionKill { id: _ } => true,
             Event::RequestKill { id: _ } => true,
             Event::RequestDaemonKill { id: _ } => true,
@@ -289,6 +457,21 @@ impl<T: Clone + ThreadSafeIsh> Event<T> {
             Event::Message(_) => true,
             Event::AllocObject { id: _, object: _ } => false,
             Event::FreeObject { id: _ } => false,
+            Event::WebSocketOpen { id: _, request: _ } => true,
+            Event::WebSocketMessage { id: _, message: _ } => true,
+            Event::WebSocketSend { id: _, message: _ } => true,
+            Event::CreateRestartableSubscriber(_) => false,
+            Event::CreateRestartableService(_) => false,
+            Event::SubscriberFaulted(_) => true,
+            Event::DaemonFaulted(_) => false,
+            Event::WakeService { id: _ } => true,
+            Event::Request { .. } => false,
+            Event::ScheduleTimer(_) => false,
+            Event::CancelTimer { id: _ } => true,
+            Event::Shutdown => true,
+            Event::Remote { .. } => true,
+            Event::MessageReply { .. } => true,
+            Event::DeadLetter(_) => true,
         }
     }
 }
@@ -310,8 +493,8 @@ impl<T: ThreadSafeIsh> Event<T> {
             Event::TcpDisconnect { id: _ } => EventType::TcpDisconnect,
             Event::NetInput { id: _, data: _ } => EventType::NetInput,
             Event::NetOutput { id: _, data: _ } => EventType::NetOutput,
-            Event::HttpRequest { id: _, request: _ } => EventType::HttpRequest,
-            Event::HttpResponse { id: _, response: _ } => EventType::HttpResponse,
+            Event::HttpRequest { .. } => EventType::HttpRequest,
+            Event::HttpResponse { .. } => EventType::HttpResponse,
             Event::RequestConnectionKill { id: _ } => EventType::RequestConnectionKill,
             Event::RequestDaemonKill { id: _ } => EventType::RequestDaemonKill,
             Event::RequestServiceKill { id: _ } => EventType::RequestServiceKill,
@@ -321,19 +504,135 @@ impl<T: ThreadSafeIsh> Event<T> {
             Event::Message(_) => EventType::Message,
             Event::AllocObject { id: _, object: _ } => EventType::AllocObject,
             Event::FreeObject { id: _ } => EventType::FreeObject,
+            Event::WebSocketOpen { id: _, request: _ } => EventType::WebSocketOpen,
+            Event::WebSocketMessage { id: _, message: _ } => EventType::WebSocketMessage,
+            Event::WebSocketSend { id: _, message: _ } => EventType::WebSocketSend,
+            Event::CreateRestartableSubscriber(_) => EventType::CreateRestartableSubscriber,
+            Event::CreateRestartableService(_) => EventType::CreateRestartableService,
+            Event::SubscriberFaulted(_) => EventType::SubscriberFaulted,
+            Event::DaemonFaulted(_) => EventType::DaemonFaulted,
+            Event::WakeService { id: _ } => EventType::WakeService,
+            Event::Request { .. } => EventType::Request,
+            Event::ScheduleTimer(_) => EventType::ScheduleTimer,
+            Event::CancelTimer { id: _ } => EventType::CancelTimer,
+            Event::Shutdown => EventType::Shutdown,
+            Event::Remote { .. } => EventType::Remote,
+            Event::MessageReply { .. } => EventType::MessageReply,
+            Event::DeadLetter(_) => EventType::DeadLetter,
         }
     }
 }
 
+/*
+* interested subscribers are offered an event one at a time in ascending SubId order, so high
+* priority subscribers (ids below 2048) always go first. everyone answering Shared before the first
+* Owned answer sees the event by reference, the first Owned answer takes it by value and nobody
+* after it is asked
+* */
 pub enum EventRequest {
     None,
     Shared,
     Owned,
 }
 
+/*
+* what a subscriber wants to be offered, read once when it is created. None on either side means
+* everything, so Subscriptions::none().topic("chat") only ever sees user events with the chat key
+* while Subscriptions::all() is offered everything like before
+* */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Subscriptions {
+    pub topics: Option<BTreeSet<String>>,
+    pub event_types: Option<BTreeSet<EventType>>,
+}
+
+impl Subscriptions {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn none() -> Self {
+        Self {
+            topics: Some(BTreeSet::new()),
+            event_types: Some(BTreeSet::new()),
+        }
+    }
+
+    /*
+     * user events whose key (see EventHandler::set_topic_key) is topic
+     * */
+    pub fn topic(mut self, topic: &str) -> Self {
+        self.topics
+            .get_or_insert_default()
+            .insert(topic.to_string());
+        self
+    }
+
+    /*
+     * global events of this type, UserDefined here means every user event gets offered to
+     * wants_global_event as well
+     * */
+    pub fn event_type(mut self, event_type: EventType) -> Self {
+        self.event_types.get_or_insert_default().insert(event_type);
+        self
+    }
+}
+
+struct SubIndex<K: Ord> {
+    everything: BTreeSet<SubId>,
+    by_key: BTreeMap<K, BTreeSet<SubId>>,
+}
+
+impl<K: Ord + Clone> SubIndex<K> {
+    fn new() -> Self {
+        Self {
+            everything: BTreeSet::new(),
+            by_key: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, id: SubId, keys: &Option<BTreeSet<K>>) {
+        let Some(keys) = keys else {
+            self.everything.insert(id);
+            return;
+        };
+        for k in keys {
+            self.by_key.entry(k.clone()).or_default().insert(id);
+        }
+    }
+
+    fn remove(&mut self, id: SubId) {
+        self.everything.remove(&id);
+        self.by_key.retain(|_, ids| {
+            ids.remove(&id);
+            !ids.is_empty()
+        });
+    }
+
+    fn candidates<Q: Ord + ?Sized>(&self, key: Option<&Q>) -> Vec<SubId>
+    where
+        K: std::borrow::Borrow<Q>,
+    {
+        let keyed = key.and_then(|k| self.by_key.get(k));
+        match keyed {
+            Some(ids) => self.everything.union(ids).copied().collect(),
+            None => self.everything.iter().copied().collect(),
+        }
+    }
+}
+
 #[async_trait]
 pub trait EventSub<T: ThreadSafeIsh>: ThreadSafeIsh {
     async fn on_create(&mut self, self_id: SubId, sender: EventSync<T>);
+
+    /*
+     * asked right after on_create, wants_event and wants_global_event only get called for events
+     * that match
+     * */
+    fn subscriptions(&self) -> Subscriptions {
+        Subscriptions::all()
+    }
+
     async fn wants_event(&self, event: &T) -> Throws<EventRequest> {
         _ = event;
         Ok(EventRequest::None)
@@ -351,6 +650,23 @@ pub trait EventSub<T: ThreadSafeIsh>: ThreadSafeIsh {
 
     async fn on_event(&mut self, event: &T) -> Throws<()>;
 
+    /*
+     * the first subscriber (in SubId order) answering true gets on_request, if none does the
+     * request fails right away
+     * */
+    async fn wants_request(&self, event: &T) -> Throws<bool> {
+        _ = event;
+        Ok(false)
+    }
+
+    /*
+     * reply can be kept and answered later, dropping it fails the request
+     * */
+    async fn on_request(&mut self, event: T, reply: Reply) -> Throws<()> {
+        _ = (event, reply);
+        Ok(())
+    }
+
     async fn on_global_event<'a>(&'a self, event: &Event<T>) -> Throws<()> {
         _ = event;
         Ok(())
@@ -360,47 +676,365 @@ pub trait EventSub<T: ThreadSafeIsh>: ThreadSafeIsh {
         _ = event;
         Ok(())
     }
+
+    /*
+     * called once while the handler shuts down, after the daemons have stopped
+     * */
+    async fn on_shutdown(&mut self) -> Throws<()> {
+        Ok(())
+    }
 }
 
 #[async_trait]
 pub trait Service<T: ThreadSafeIsh>: ThreadSafeIsh {
     async fn create(&mut self, id: ServiceId, sender: EventSync<T>);
     async fn update(&mut self) -> Throws<()>;
+
+    /*
+     * how long after an update the next one runs, None means the service only runs once after
+     * creation and then whenever something calls EventSync::wake_service with its id
+     * */
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(DEFAULT_TICK_INTERVAL)
+    }
+
+    async fn on_shutdown(&mut self) -> Throws<()> {
+        Ok(())
+    }
 }
 
+pub const DEFAULT_TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*
+* stopping a daemon drops its run future at whatever await it is parked on and then calls
+* on_shutdown on the same value, so anything run left half done can be cleaned up there
+* */
 #[async_trait]
 pub trait Daemon: ThreadSafeIsh {
     async fn run(&mut self);
+
+    async fn on_shutdown(&mut self) {}
+}
+
+struct DaemonTask {
+    stop: Option<tokio::sync::oneshot::Sender<()>>,
+    join: tokio::task::JoinHandle<()>,
+}
+
+impl DaemonTask {
+    fn spawn(mut daemon: Box<dyn Daemon>) -> Self {
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let join = tokio::task::spawn(async move {
+            tokio::select! {
+                _ = daemon.run() => {}
+                _ = stopped => {}
+            }
+            daemon.on_shutdown().await;
+        });
+        Self {
+            stop: Some(stop),
+            join,
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            _ = stop.send(());
+        }
+    }
+}
+pub type SubscriberFactory<T> = Arc<dyn Fn() -> Box<dyn EventSub<T>> + Send + Sync>;
+pub type ServiceFactory<T> = Arc<dyn Fn() -> Box<dyn Service<T>> + Send + Sync>;
+pub type ErrorSink = Arc<dyn Fn(&FaultReport, &Exception) + Send + Sync>;
+pub type TopicKey<T> = Arc<dyn Fn(&T) -> String + Send + Sync>;
+
+type TimerFn<T> = Box<dyn FnMut() -> Option<T> + Send + Sync>;
+
+/*
+* where the handler reads the time for timers and service ticks, Virtual only moves when
+* EventHarness::advance moves it
+* */
+#[derive(Clone)]
+enum Clock {
+    Real,
+    Virtual(Arc<Mutex<Instant>>),
+}
+
+impl Clock {
+    fn now(&self) -> Instant {
+        match self {
+            Clock::Real => Instant::now(),
+            Clock::Virtual(now) => *now.lock().unwrap(),
+        }
+    }
+}
+
+/*
+* a pending UserDefined event, next hands out the event to deliver each time the timer fires and
+* None once it is used up
+* */
+pub struct Timer<T: ThreadSafeIsh> {
+    id: TimerId,
+    at: Instant,
+    after: Option<Duration>,
+    every: Option<Duration>,
+    next: TimerFn<T>,
+}
+
+impl<T: ThreadSafeIsh> Timer<T> {
+    pub fn id(&self) -> TimerId {
+        self.id
+    }
+
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+}
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/*
+* how many recent origins a handler remembers to drop remote events that reach it twice
+* */
+pub const REMOTE_DEDUP_WINDOW: usize = 4096;
+
+/*
+* where a bridged event was first sent, node is the EventSync::node_id of the handler it came
+* from and seq counts up per node
+* */
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Origin {
+    pub node: u64,
+    pub seq: u64,
+}
+
+/*
+* the parts of an Event that can cross to another handler
+* */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RemoteEvent<T> {
+    User(T),
+    Message(Message),
+    Reply {
+        id: MessageId,
+        to: u64,
+        result: Result<Vec<u8>, CallError>,
+    },
+}
+
+/*
+* the answering half of EventSync::request
+* */
+pub struct Reply {
+    slot: WriteOnce<Throws<Box<dyn Any + Send>>>,
+}
+
+impl Reply {
+    pub fn send<R: Any + Send>(self, value: R) {
+        self.slot.write(Ok(Box::new(value)));
+    }
+
+    pub fn fail(self, error: Exception) {
+        self.slot.write(Err(error));
+    }
+}
+
+/*
+* what threw, Handler covers failures in the event loop itself and Daemon covers errors daemons
+* report through EventSync::report_fault, neither of which can be removed or restarted
+* */
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum FaultSource {
+    Subscriber(SubId),
+    Service(ServiceId),
+    Handler,
+    Daemon,
+}
+
+/*
+* what EventHandler does when a subscriber or service returns an error, Restart rebuilds it from
+* the factory it was created with and falls back to Remove for ones that have none. Escalate makes
+* EventHandler::run return the exception
+* */
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum SupervisionPolicy {
+    #[default]
+    LogAndContinue,
+    Remove,
+    Restart,
+    Escalate,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FaultAction {
+    Continued,
+    Removed,
+    Restarted,
+    Escalated,
+}
+
+/*
+* the clonable part of an exception, goes out as Event::SubscriberFaulted
+* */
+#[derive(Clone, Debug)]
+pub struct FaultReport {
+    pub source: FaultSource,
+    pub action: FaultAction,
+    pub error: Arc<str>,
+    pub file: &'static str,
+    pub line: u32,
+}
+
+struct Fault {
+    source: FaultSource,
+    error: Exception,
+    quiet: bool,
 }
+
 struct Handler<T: ThreadSafeIsh> {
     subscribers: BTreeMap<SubId, Box<dyn EventSub<T>>>,
     services: BTreeMap<ServiceId, Box<dyn Service<T>>>,
-    daemons: BTreeSet<DaemonId>,
+    daemons: BTreeMap<DaemonId, DaemonTask>,
     objects: BTreeMap<ObjectId, Box<dyn Object>>,
-    sender: Sender<Event<T>>,
+    sender: EventSender<T>,
+    subscriber_factories: BTreeMap<SubId, SubscriberFactory<T>>,
+    service_factories: BTreeMap<ServiceId, ServiceFactory<T>>,
+    policy: SupervisionPolicy,
+    error_sink: Option<ErrorSink>,
+    faults: Vec<Fault>,
+    in_fault_event: bool,
+    next_tick: BTreeMap<ServiceId, Instant>,
+    woken: BTreeSet<ServiceId>,
+    topic_key: Option<TopicKey<T>>,
+    user_index: SubIndex<String>,
+    global_index: SubIndex<EventType>,
+    timers: BTreeMap<TimerId, Timer<T>>,
+    timer_heap: BinaryHeap<Reverse<(Instant, TimerId)>>,
+    shutting_down: bool,
+    shutdown_timeout: Duration,
+    tracer: Option<Box<dyn EventTracer<T>>>,
+    replaying: bool,
+    clock: Clock,
+    remote_seen: BTreeSet<Origin>,
+    remote_order: VecDeque<Origin>,
 }
 
 pub struct EventHandler<T: ThreadSafeIsh> {
-    pub channel: Receiver<Event<T>>,
+    pub channel: EventReceiver<T>,
     handler: Handler<T>,
 }
 
 impl<T: ThreadSafeIsh> Handler<T> {
-    fn new(sender: Sender<Event<T>>) -> Self {
+    fn new(sender: EventSender<T>) -> Self {
         Self {
             subscribers: BTreeMap::new(),
-            sender,
+            sender: sender.with_source(EventSource::Handler),
             services: BTreeMap::new(),
-            daemons: BTreeSet::new(),
+            daemons: BTreeMap::new(),
             objects: BTreeMap::new(),
+            subscriber_factories: BTreeMap::new(),
+            service_factories: BTreeMap::new(),
+            policy: SupervisionPolicy::default(),
+            error_sink: None,
+            faults: Vec::new(),
+            in_fault_event: false,
+            next_tick: BTreeMap::new(),
+            woken: BTreeSet::new(),
+            topic_key: None,
+            user_index: SubIndex::new(),
+            global_index: SubIndex::new(),
+            timers: BTreeMap::new(),
+            timer_heap: BinaryHeap::new(),
+            shutting_down: false,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            tracer: None,
+            replaying: false,
+            clock: Clock::Real,
+            remote_seen: BTreeSet::new(),
+            remote_order: VecDeque::new(),
+        }
+    }
+
+    fn schedule(&mut self, timer: Timer<T>) {
+        self.timer_heap.push(Reverse((timer.at, timer.id)));
+        self.timers.insert(timer.id, timer);
+    }
+
+    /*
+     * heap entries of cancelled or rescheduled timers are left in place and skipped here, a
+     * repeating timer that fell behind fires once and then goes back to its period instead of
+     * catching up on every missed tick
+     * */
+    fn fire_timers(&mut self) -> Throws<()> {
+        if self.replaying {
+            return Ok(());
+        }
+        let now = self.now();
+        while let Some(Reverse((at, id))) = self.timer_heap.peek().copied() {
+            if at > now {
+                break;
+            }
+            self.timer_heap.pop();
+            let Some(mut timer) = self.timers.remove(&id) else {
+                continue;
+            };
+            if timer.at != at {
+                self.timers.insert(id, timer);
+                continue;
+            }
+            let Some(ev) = (timer.next)() else {
+                continue;
+            };
+            self.sender
+                .with_source(EventSource::Timer(id))
+                .force_send(Event::UserDefined(ev))?;
+            if let Some(every) = timer.every {
+                timer.at = (at + every).max(now);
+                self.schedule(timer);
+            }
+        }
+        Ok(())
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let tick = self.next_tick.values().min().copied();
+        let timer = self.timer_heap.peek().map(|x| x.0.0);
+        match (tick, timer) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
         }
     }
 
+    fn index_subscriber(&mut self, id: SubId) {
+        self.user_index.remove(id);
+        self.global_index.remove(id);
+        let Some(sub) = self.subscribers.get(&id) else {
+            return;
+        };
+        let subs = sub.subscriptions();
+        self.user_index.insert(id, &subs.topics);
+        self.global_index.insert(id, &subs.event_types);
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn fault(&mut self, source: FaultSource, error: Exception) {
+        self.faults.push(Fault {
+            source,
+            error,
+            quiet: self.in_fault_event,
+        });
+    }
+
     pub async fn create_subscriber(
         &mut self,
         mut event_sub: Box<dyn EventSub<T>>,
-        sender: Sender<Event<T>>,
-    ) {
+        sender: EventSender<T>,
+    ) -> SubId {
         let mut min = 2048;
         for i in 2048..=u64::MAX {
             if !self.subscribers.contains_key(&SubId { inner: i }) {
@@ -411,16 +1045,19 @@ impl<T: ThreadSafeIsh> Handler<T> {
         if min == u64::MAX {
             panic!();
         }
+        let sender = sender.with_source(EventSource::Subscriber(SubId { inner: min }));
         event_sub
             .as_mut()
             .on_create(SubId { inner: min }, EventSync::new(sender))
             .await;
         self.subscribers.insert(SubId { inner: min }, event_sub);
+        self.index_subscriber(SubId { inner: min });
+        SubId { inner: min }
     }
     pub async fn create_subscriber_high_priority(
         &mut self,
         mut event_sub: Box<dyn EventSub<T>>,
-        sender: Sender<Event<T>>,
+        sender: EventSender<T>,
     ) {
         let mut min = 0;
         for i in 0..=u64::MAX {
@@ -432,25 +1069,30 @@ impl<T: ThreadSafeIsh> Handler<T> {
         if min == u64::MAX {
             panic!();
         }
+        let sender = sender.with_source(EventSource::Subscriber(SubId { inner: min }));
         event_sub
             .as_mut()
             .on_create(SubId { inner: min }, EventSync::new(sender))
             .await;
         self.subscribers.insert(SubId { inner: min }, event_sub);
+        self.index_subscriber(SubId { inner: min });
     }
 
     pub async fn destroy_subscriber(&mut self, id: SubId) {
         self.subscribers.remove(&id);
+        self.subscriber_factories.remove(&id);
+        self.user_index.remove(id);
+        self.global_index.remove(id);
     }
 
     pub async fn create_service(
         &mut self,
         mut service: Box<dyn Service<T>>,
-        sender: Sender<Event<T>>,
-    ) {
+        sender: EventSender<T>,
+    ) -> ServiceId {
         let mut min = 2048;
         for i in 2048..=u64::MAX {
-            if !self.services.contains_key(&ServiceId { inner: min }) {
+            if !self.services.contains_key(&ServiceId { inner: i }) {
                 min = i;
                 break;
             }
@@ -458,20 +1100,23 @@ impl<T: ThreadSafeIsh> Handler<T> {
         if min == u64::MAX {
             panic!();
         }
+        let sender = sender.with_source(EventSource::Service(ServiceId { inner: min }));
         service
             .create(ServiceId { inner: min }, EventSync::new(sender))
             .await;
         self.services.insert(ServiceId { inner: min }, service);
+        self.next_tick.insert(ServiceId { inner: min }, self.now());
+        ServiceId { inner: min }
     }
 
     pub async fn create_service_high_priority(
         &mut self,
         mut service: Box<dyn Service<T>>,
-        sender: Sender<Event<T>>,
+        sender: EventSender<T>,
     ) {
         let mut min = 0;
         for i in 0..=u64::MAX {
-            if !self.services.contains_key(&ServiceId { inner: min }) {
+            if !self.services.contains_key(&ServiceId { inner: i }) {
                 min = i;
                 break;
             }
@@ -479,51 +1124,222 @@ impl<T: ThreadSafeIsh> Handler<T> {
         if min == u64::MAX {
             panic!();
         }
+        let sender = sender.with_source(EventSource::Service(ServiceId { inner: min }));
         service
             .create(ServiceId { inner: min }, EventSync::new(sender))
             .await;
         self.services.insert(ServiceId { inner: min }, service);
+        self.next_tick.insert(ServiceId { inner: min }, self.now());
     }
 
     pub async fn destroy_service(&mut self, id: ServiceId) {
         self.services.remove(&id);
+        self.service_factories.remove(&id);
+        self.next_tick.remove(&id);
+        self.woken.remove(&id);
     }
 
+    /*
+     * a subscriber that throws is recorded as a fault and skipped, the rest still see the event
+     * */
     pub async fn handle_user_event(&mut self, ev: T) -> Throws<()> {
-        for i in &mut self.subscribers {
-            let v = i.1.wants_event(&ev).await?;
+        let mut faults = Vec::new();
+        let topic = self.topic_key.as_ref().map(|key| key(&ev));
+        for id in self.user_index.candidates(topic.as_deref()) {
+            let Some(sub) = self.subscribers.get_mut(&id) else {
+                continue;
+            };
+            let v = match sub.wants_event(&ev).await {
+                Ok(v) => v,
+                Err(e) => {
+                    faults.push((FaultSource::Subscriber(id), e));
+                    continue;
+                }
+            };
             match v {
                 EventRequest::None => {
                     continue;
                 }
                 EventRequest::Shared => {
-                    i.1.as_mut().on_event(&ev).await?;
+                    if let Err(e) = sub.as_mut().on_event(&ev).await {
+                        faults.push((FaultSource::Subscriber(id), e));
+                    }
                 }
                 EventRequest::Owned => {
-                    i.1.as_mut().on_event_owned(ev).await?;
+                    if let Err(e) = sub.as_mut().on_event_owned(ev).await {
+                        faults.push((FaultSource::Subscriber(id), e));
+                    }
+                    break;
+                }
+            }
+        }
+        for (source, e) in faults {
+            self.fault(source, e);
+        }
+        Ok(())
+    }
+
+    pub async fn handle_request(&mut self, ev: T, reply: Reply) {
+        let topic = self.topic_key.as_ref().map(|key| key(&ev));
+        let mut claimed = None;
+        for id in self.user_index.candidates(topic.as_deref()) {
+            let Some(sub) = self.subscribers.get(&id) else {
+                continue;
+            };
+            match sub.wants_request(&ev).await {
+                Ok(true) => {
+                    claimed = Some(id);
                     break;
                 }
+                Ok(false) => {}
+                Err(e) => self.fault(FaultSource::Subscriber(id), e),
+            }
+        }
+        let Some(id) = claimed else {
+            reply.fail("no subscriber claimed the request".into());
+            return;
+        };
+        if let Some(sub) = self.subscribers.get_mut(&id)
+            && let Err(e) = sub.on_request(ev, reply).await
+        {
+            self.fault(FaultSource::Subscriber(id), e);
+        }
+    }
+
+    async fn dispatch(&mut self, source: EventSource, ev: Event<T>) -> Throws<()> {
+        if let Some(tracer) = self.tracer.as_mut()
+            && let Err(e) = tracer.record(source, &ev)
+        {
+            self.fault(FaultSource::Handler, e);
+        }
+        self.run_event(ev).await
+    }
+
+    /*
+     * false for events this handler sent itself or has already seen, so a ring of bridges can't
+     * loop an event forever
+     * */
+    fn accept_remote(&mut self, origin: Origin) -> bool {
+        if origin.node == self.sender.node_id() || !self.remote_seen.insert(origin) {
+            return false;
+        }
+        self.remote_order.push_back(origin);
+        if self.remote_order.len() > REMOTE_DEDUP_WINDOW
+            && let Some(old) = self.remote_order.pop_front()
+        {
+            self.remote_seen.remove(&old);
+        }
+        true
+    }
+
+    /*
+     * only the node owning the target runs a message, one for another node is left to the
+     * bridges. a message to a missing object goes back out as Event::DeadLetter and a caller
+     * waiting on it gets CallError::NoSuchObject. a failed call goes back to the caller when it
+     * asked for a reply and is reported as a handler fault when nobody is listening
+     * */
+    fn deliver_message(&mut self, msg: Message) -> Throws<()> {
+        if !self.is_local_node(msg.target_id.node()) {
+            return Ok(());
+        }
+        let (id, reply, to) = (msg.id, msg.reply, msg.reply_to);
+        let Some(obj) = self.objects.get_mut(&msg.target_id) else {
+            if reply {
+                self.sender.force_send(Event::MessageReply {
+                    id,
+                    to,
+                    result: Err(CallError::NoSuchObject { id: msg.target_id }),
+                })?;
+            }
+            self.sender.force_send(Event::DeadLetter(msg))?;
+            return Ok(());
+        };
+        match obj.as_mut().call(msg) {
+            Ok(result) if reply => {
+                self.sender.force_send(Event::MessageReply {
+                    id,
+                    to,
+                    result: Ok(result),
+                })?;
             }
+            Ok(_) => {}
+            Err(e) if reply => {
+                self.sender.force_send(Event::MessageReply {
+                    id,
+                    to,
+                    result: Err(e.into()),
+                })?;
+            }
+            Err(e) => self.fault(FaultSource::Handler, e),
         }
         Ok(())
     }
 
+    /*
+     * node 0 is what ids and replies made without a handler in mind carry, they stay wherever
+     * they are sent
+     * */
+    fn is_local_node(&self, node: u64) -> bool {
+        node == 0 || node == self.sender.node_id()
+    }
+
+    fn deliver_reply(&self, id: MessageId, to: u64, result: Result<Vec<u8>, CallError>) {
+        if self.is_local_node(to)
+            && let Some(slot) = self.sender.take_reply(id)
+        {
+            slot.write(result);
+        }
+    }
+
     pub async fn run_event(&mut self, i: Event<T>) -> Throws<()> {
-        for (_, sub) in &mut self.subscribers {
-            match sub.as_ref().wants_global_event(&i).await? {
+        if let Event::Remote { origin, .. } = &i
+            && !self.accept_remote(*origin)
+        {
+            return Ok(());
+        }
+        self.in_fault_event = matches!(i, Event::SubscriberFaulted(_));
+        let mut faults = Vec::new();
+        let mut taken = None;
+        for id in self.global_index.candidates(Some(&i.get_type())) {
+            let Some(sub) = self.subscribers.get_mut(&id) else {
+                continue;
+            };
+            let v = match sub.as_ref().wants_global_event(&i).await {
+                Ok(v) => v,
+                Err(e) => {
+                    faults.push((FaultSource::Subscriber(id), e));
+                    continue;
+                }
+            };
+            match v {
                 EventRequest::None => {
                     continue;
                 }
                 EventRequest::Shared => {
-                    sub.as_mut().on_global_event(&i).await?;
+                    if let Err(e) = sub.as_mut().on_global_event(&i).await {
+                        faults.push((FaultSource::Subscriber(id), e));
+                    }
                 }
                 EventRequest::Owned => {
-                    sub.as_mut().on_global_event_owned(i).await?;
-                    return Ok(());
+                    taken = Some(id);
+                    break;
                 }
             }
         }
-        println!("LOG:{:#?}", i.get_type());
+        if let Some(id) = taken {
+            if let Some(sub) = self.subscribers.get_mut(&id)
+                && let Err(e) = sub.as_mut().on_global_event_owned(i).await
+            {
+                faults.push((FaultSource::Subscriber(id), e));
+            }
+            for (source, e) in faults {
+                self.fault(source, e);
+            }
+            return Ok(());
+        }
+        for (source, e) in faults {
+            self.fault(source, e);
+        }
         match i {
             Event::CreateSubscriber(event_sub, high_priority) => {
                 if high_priority {
@@ -550,41 +1366,84 @@ impl<T: ThreadSafeIsh> Handler<T> {
             Event::UserDefined(x) => {
                 self.handle_user_event(x).await?;
             }
-            Event::CreateDaemon { mut daemon, id } => {
-                tokio::task::spawn(async move { daemon.run().await });
-                self.daemons.insert(id);
-                self.sender.send(Event::DaemonCreated { id })?;
+            Event::Request { event, reply } => {
+                self.handle_request(event, reply).await;
+            }
+            Event::ScheduleTimer(mut timer) => {
+                if let Some(after) = timer.after.take() {
+                    timer.at = self.now() + after;
+                }
+                self.schedule(timer);
+            }
+            Event::CancelTimer { id } => {
+                self.timers.remove(&id);
+            }
+            Event::CreateDaemon { daemon, id } => {
+                self.daemons.insert(id, DaemonTask::spawn(daemon));
+                self.sender.force_send(Event::DaemonCreated { id })?;
             }
             Event::RequestDaemonKill { id } => {
-                self.daemons.remove(&id);
+                if let Some(mut task) = self.daemons.remove(&id) {
+                    task.stop();
+                }
                 id.free();
             }
+            Event::Shutdown => {
+                self.shutting_down = true;
+            }
+            Event::DaemonFaulted(error) => {
+                self.fault(FaultSource::Daemon, error);
+            }
             Event::RequestServiceKill { id } => {
-                self.services.remove(&id);
+                self.destroy_service(id).await;
             }
             Event::RequestSubscriberKill { id } => {
-                self.subscribers.remove(&id);
+                self.destroy_subscriber(id).await;
+            }
+            Event::CreateRestartableSubscriber(factory) => {
+                let id = self.create_subscriber(factory(), self.sender.clone()).await;
+                self.subscriber_factories.insert(id, factory);
+            }
+            Event::CreateRestartableService(factory) => {
+                let id = self.create_service(factory(), self.sender.clone()).await;
+                self.service_factories.insert(id, factory);
             }
-            Event::KeyBoardInput { key_code: _ } => {
-                todo!();
+            Event::WakeService { id } if self.services.contains_key(&id) => {
+                self.woken.insert(id);
             }
+            Event::WakeService { id: _ } => {}
+            Event::KeyBoardInput { key_code: _ } => {}
             Event::MouseInput {
                 input_mouse_input: _,
-            } => {
-                todo!();
-            }
+            } => {}
             Event::Message(msg) => {
-                let id = msg.target_id;
-                if let Some(obj) = self.objects.get_mut(&id) {
-                    obj.as_mut().call(msg);
-                }
+                self.deliver_message(msg)?;
             }
-            Event::AllocObject { id, object } => {
-                self.objects.insert(id, object);
+            Event::MessageReply { id, to, result } => {
+                self.deliver_reply(id, to, result);
+            }
+            Event::Remote { origin: _, event } => match event {
+                RemoteEvent::User(x) => {
+                    self.handle_user_event(x).await?;
+                }
+                RemoteEvent::Message(msg) => {
+                    self.deliver_message(msg)?;
+                }
+                RemoteEvent::Reply { id, to, result } => {
+                    self.deliver_reply(id, to, result);
+                }
+            },
+            Event::AllocObject { id, object } => {
+                self.objects.insert(id, object);
             }
             Event::FreeObject { id } => {
                 self.objects.remove(&id);
-                id.free();
+                self.sender
+                    .queue
+                    .names
+                    .lock()
+                    .unwrap()
+                    .retain(|_, x| *x != id);
             }
             _ => {}
         }
@@ -592,9 +1451,110 @@ impl<T: ThreadSafeIsh> Handler<T> {
     }
 }
 
+impl<T: ThreadSafeIsh> Handler<T> {
+    /*
+     * restarts keep the id so anything holding it still reaches the new instance
+     * */
+    async fn restart(&mut self, source: FaultSource) -> FaultAction {
+        match source {
+            FaultSource::Subscriber(id) => {
+                let Some(factory) = self.subscriber_factories.get(&id).cloned() else {
+                    self.destroy_subscriber(id).await;
+                    return FaultAction::Removed;
+                };
+                let mut sub = factory();
+                let sender = self.sender.with_source(EventSource::Subscriber(id));
+                sub.on_create(id, EventSync::new(sender)).await;
+                self.subscribers.insert(id, sub);
+                self.index_subscriber(id);
+                FaultAction::Restarted
+            }
+            FaultSource::Service(id) => {
+                let Some(factory) = self.service_factories.get(&id).cloned() else {
+                    self.destroy_service(id).await;
+                    return FaultAction::Removed;
+                };
+                let mut service = factory();
+                service
+                    .create(
+                        id,
+                        EventSync::new(self.sender.with_source(EventSource::Service(id))),
+                    )
+                    .await;
+                self.services.insert(id, service);
+                self.next_tick.insert(id, self.now());
+                FaultAction::Restarted
+            }
+            FaultSource::Handler | FaultSource::Daemon => FaultAction::Continued,
+        }
+    }
+
+    async fn apply_policy(&mut self, source: FaultSource) -> FaultAction {
+        match (self.policy, source) {
+            (_, FaultSource::Daemon) => FaultAction::Continued,
+            (SupervisionPolicy::Escalate, _) => FaultAction::Escalated,
+            (SupervisionPolicy::LogAndContinue, _) | (_, FaultSource::Handler) => {
+                FaultAction::Continued
+            }
+            (SupervisionPolicy::Remove, FaultSource::Subscriber(id)) => {
+                self.destroy_subscriber(id).await;
+                FaultAction::Removed
+            }
+            (SupervisionPolicy::Remove, FaultSource::Service(id)) => {
+                self.destroy_service(id).await;
+                FaultAction::Removed
+            }
+            (SupervisionPolicy::Restart, source) => self.restart(source).await,
+        }
+    }
+
+    /*
+     * faults raised while handling a SubscriberFaulted event don't produce another one, otherwise
+     * a subscriber that throws on everything would keep the loop busy forever
+     * */
+    async fn supervise(&mut self) -> Throws<()> {
+        let mut escalated = None;
+        for fault in std::mem::take(&mut self.faults) {
+            let action = self.apply_policy(fault.source).await;
+            let report = FaultReport {
+                source: fault.source,
+                action,
+                error: fault.error.error.to_string().into(),
+                file: fault.error.file,
+                line: fault.error.line,
+            };
+            match &self.error_sink {
+                Some(sink) => sink(&report, &fault.error),
+                None => println!(
+                    "{:?} threw exception:{} line:{} file:{}, {:?}",
+                    report.source, report.error, report.line, report.file, report.action
+                ),
+            }
+            if !fault.quiet {
+                self.sender.force_send(Event::SubscriberFaulted(report))?;
+            }
+            if action == FaultAction::Escalated && escalated.is_none() {
+                escalated = Some(fault.error);
+            }
+        }
+        match escalated {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
 impl<T: ThreadSafeIsh> EventHandler<T> {
-    pub fn new() -> (std::sync::mpsc::Sender<Event<T>>, Self) {
-        let (sender, reciever) = std::sync::mpsc::channel();
+    pub fn new() -> (EventSender<T>, Self) {
+        Self::with_capacity(DEFAULT_EVENT_CAPACITY)
+    }
+
+    /*
+     * senders past capacity queued events get SendError::Full from the sync EventSync methods or
+     * wait in the async ones, unless the event type has an OverflowPolicy saying otherwise
+     * */
+    pub fn with_capacity(capacity: usize) -> (EventSender<T>, Self) {
+        let (sender, reciever) = event_queue(capacity);
         (
             sender.clone(),
             Self {
@@ -605,17 +1565,18 @@ impl<T: ThreadSafeIsh> EventHandler<T> {
     }
 
     pub async fn handle_events(&mut self) -> Throws<()> {
+        self.handler.fire_timers()?;
         loop {
-            let ev = self.channel.try_recv();
+            let ev = self.channel.try_recv_sourced();
             match ev {
-                Ok(ev) => {
-                    self.handler.run_event(ev).await?;
+                Ok((source, ev)) => {
+                    self.handler.dispatch(source, ev).await?;
                 }
                 Err(e) => match e {
-                    std::sync::mpsc::TryRecvError::Empty => {
+                    TryRecvError::Empty => {
                         break;
                     }
-                    std::sync::mpsc::TryRecvError::Disconnected => {
+                    TryRecvError::Disconnected => {
                         return Err("disconnected".into());
                     }
                 },
@@ -624,30 +1585,711 @@ impl<T: ThreadSafeIsh> EventHandler<T> {
         Ok(())
     }
 
+    /*
+     * only services that were woken or whose tick interval ran out get updated
+     * */
     pub async fn handle_services(&mut self) -> Throws<()> {
-        for service in self.handler.services.values_mut() {
-            service.as_mut().update().await?;
+        self.update_services().await?;
+        Ok(())
+    }
+
+    async fn update_services(&mut self) -> Throws<usize> {
+        let mut faults = Vec::new();
+        let mut updated = 0;
+        self.handler.in_fault_event = false;
+        let clock = self.handler.clock.clone();
+        let now = clock.now();
+        let woken = std::mem::take(&mut self.handler.woken);
+        for (id, service) in self.handler.services.iter_mut() {
+            let due = self.handler.next_tick.get(id).is_some_and(|x| *x <= now);
+            if !due && !woken.contains(id) {
+                continue;
+            }
+            updated += 1;
+            if let Err(e) = service.as_mut().update().await {
+                faults.push((FaultSource::Service(*id), e));
+            }
+            match service.tick_interval() {
+                Some(interval) => {
+                    self.handler.next_tick.insert(*id, clock.now() + interval);
+                }
+                None => {
+                    self.handler.next_tick.remove(id);
+                }
+            }
+        }
+        for (source, e) in faults {
+            self.handler.fault(source, e);
+        }
+        Ok(updated)
+    }
+
+    pub fn set_overflow_policy(&mut self, event_type: EventType, policy: OverflowPolicy) {
+        self.channel.set_overflow_policy(event_type, policy);
+    }
+
+    pub fn set_supervision_policy(&mut self, policy: SupervisionPolicy) {
+        self.handler.policy = policy;
+    }
+
+    /*
+     * maps a user event to the topic matched against Subscriptions::topic, without one only
+     * subscribers without a topic filter see user events
+     * */
+    pub fn set_topic_key(&mut self, key: impl Fn(&T) -> String + Send + Sync + 'static) {
+        self.handler.topic_key = Some(Arc::new(key));
+    }
+
+    /*
+     * gets every fault with the full exception, without one they are printed
+     * */
+    pub fn set_error_sink(
+        &mut self,
+        sink: impl Fn(&FaultReport, &Exception) + Send + Sync + 'static,
+    ) {
+        self.handler.error_sink = Some(Arc::new(sink));
+    }
+
+    /*
+     * applies the supervision policy to everything that threw since the last call, returns the
+     * exception when the policy is Escalate
+     * */
+    pub async fn supervise(&mut self) -> Throws<()> {
+        self.handler.supervise().await
+    }
+
+    /*
+     * sleeps until an event arrives or the next service tick is due
+     */
+    async fn wait(&mut self) -> Throws<()> {
+        if !self.handler.woken.is_empty() {
+            return Ok(());
+        }
+        let recieved = match self.handler.next_deadline() {
+            Some(deadline) => {
+                tokio::select! {
+                    ev = self.channel.recv_sourced() => ev,
+                    _ = tokio::time::sleep_until(deadline) => return Ok(()),
+                }
+            }
+            None => self.channel.recv_sourced().await,
+        };
+        let Some((source, ev)) = recieved else {
+            throw!("disconnected");
+        };
+        self.handler.dispatch(source, ev).await
+    }
+
+    /*
+     * returns once an Event::Shutdown has been handled and shutdown finished
+     * */
+    pub async fn run(&mut self, setup: impl AsyncFn(EventSync<T>)) -> Throws<()> {
+        setup(EventSync::new(
+            self.handler.sender.with_source(EventSource::External),
+        ))
+        .await;
+        loop {
+            if let Err(e) = self.wait().await {
+                self.handler.fault(FaultSource::Handler, e);
+            }
+            if let Err(e) = self.handle_events().await {
+                self.handler.fault(FaultSource::Handler, e);
+            }
+            if self.handler.shutting_down {
+                return self.shutdown().await;
+            }
+            self.handle_services().await?;
+            self.supervise().await?;
+        }
+    }
+
+    /*
+     * every event the handler takes off its queue is passed to tracer first
+     * */
+    pub fn set_tracer(&mut self, tracer: impl EventTracer<T>) {
+        self.handler.tracer = Some(Box::new(tracer));
+    }
+
+    /*
+     * feeds a recorded session back in following the recorded dispatch order. UserDefined events
+     * that came from outside (External and Timer) are dispatched straight from the record, for
+     * everything the subscribers, services or handler sent the next queued event is dispatched
+     * instead since the replay sends it again itself. timers don't fire on their own while
+     * replaying, set the handler up with the same subscribers as the recorded one first
+     * */
+    pub async fn replay(
+        &mut self,
+        records: impl IntoIterator<Item = TraceRecord<T>>,
+    ) -> Throws<()> {
+        self.handler.replaying = true;
+        let out = self.replay_records(records).await;
+        self.handler.replaying = false;
+        out
+    }
+
+    async fn replay_records(
+        &mut self,
+        records: impl IntoIterator<Item = TraceRecord<T>>,
+    ) -> Throws<()> {
+        for record in records {
+            if matches!(record.source, EventSource::External | EventSource::Timer(_)) {
+                let Some(payload) = record.payload else {
+                    continue;
+                };
+                self.handler
+                    .dispatch(record.source, Event::UserDefined(payload))
+                    .await?;
+            } else if let Ok((source, ev)) = self.channel.try_recv_sourced() {
+                self.handler.dispatch(source, ev).await?;
+            }
+            self.supervise().await?;
+        }
+        self.handle_events().await
+    }
+
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.handler.shutdown_timeout = timeout;
+    }
+
+    /*
+     * stops every daemon and keeps handling events until they have all finished on_shutdown and
+     * the queue is empty, then runs the on_shutdown hooks of services and subscribers. whatever
+     * is still going when the shutdown timeout runs out is aborted or skipped
+     * */
+    pub async fn shutdown(&mut self) -> Throws<()> {
+        let deadline = Instant::now() + self.handler.shutdown_timeout;
+        self.handler.shutting_down = true;
+        for task in self.handler.daemons.values_mut() {
+            task.stop();
+        }
+        loop {
+            if let Err(e) = self.handle_events().await {
+                self.handler.fault(FaultSource::Handler, e);
+            }
+            self.handler.daemons.retain(|_, x| !x.join.is_finished());
+            if self.handler.daemons.is_empty() && self.handler.sender.is_empty() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                for task in self.handler.daemons.values() {
+                    task.join.abort();
+                }
+                self.handler.daemons.clear();
+                break;
+            }
+            let poll = (Instant::now() + Duration::from_millis(10)).min(deadline);
+            tokio::select! {
+                ev = self.channel.recv_sourced() => {
+                    if let Some((source, ev)) = ev
+                        && let Err(e) = self.handler.dispatch(source, ev).await
+                    {
+                        self.handler.fault(FaultSource::Handler, e);
+                    }
+                }
+                _ = tokio::time::sleep_until(poll) => {}
+            }
+        }
+        let mut faults = Vec::new();
+        for (id, service) in self.handler.services.iter_mut() {
+            match tokio::time::timeout_at(deadline, service.on_shutdown()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => faults.push((FaultSource::Service(*id), e)),
+                Err(_) => break,
+            }
+        }
+        for (id, sub) in self.handler.subscribers.iter_mut() {
+            match tokio::time::timeout_at(deadline, sub.on_shutdown()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => faults.push((FaultSource::Subscriber(*id), e)),
+                Err(_) => break,
+            }
+        }
+        for (source, e) in faults {
+            self.handler.fault(source, e);
+        }
+        if matches!(self.handler.policy, SupervisionPolicy::Restart) {
+            // nothing restarted now would ever run
+            self.handler.policy = SupervisionPolicy::LogAndContinue;
         }
+        self.supervise().await
+    }
+}
+
+/*
+* what happens to an event that arrives while the queue is full. Wait makes try_send report Full
+* and send wait for room, DropNewest silently discards the new event, DropOldest discards the
+* oldest queued event of the same type (or the new one when there is none) and Coalesce replaces
+* the newest queued event of the same type even when the queue isn't full, so at most one of them
+* is ever waiting
+* */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Wait,
+    DropNewest,
+    DropOldest,
+    Coalesce,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    Full,
+    Closed,
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Full => write!(f, "event queue is full"),
+            SendError::Closed => write!(f, "event queue is closed"),
+        }
+    }
+}
+
+impl Error for SendError {}
+
+pub const DEFAULT_EVENT_CAPACITY: usize = 8192;
+
+/*
+* who put an event on the queue, External is anything holding the sender EventHandler::new
+* returned
+* */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum EventSource {
+    External,
+    Handler,
+    Subscriber(SubId),
+    Service(ServiceId),
+    Timer(TimerId),
+}
+
+struct QueueState<T: ThreadSafeIsh> {
+    events: VecDeque<(EventSource, Event<T>)>,
+    policies: BTreeMap<EventType, OverflowPolicy>,
+    senders: usize,
+    closed: bool,
+}
+
+type ReplySlot = WriteOnce<Result<Vec<u8>, CallError>>;
+
+struct EventQueue<T: ThreadSafeIsh> {
+    state: Mutex<QueueState<T>>,
+    capacity: usize,
+    node: u64,
+    origin_seq: AtomicU64,
+    replies: Mutex<BTreeMap<MessageId, ReplySlot>>,
+    names: Mutex<BTreeMap<Arc<str>, ObjectId>>,
+    readable: tokio::sync::Notify,
+    writable: tokio::sync::Notify,
+}
+
+impl<T: ThreadSafeIsh> EventQueue<T> {
+    /*
+     * takes the event out of slot unless it has to wait for room or the queue is closed
+     * */
+    fn push(
+        &self,
+        slot: &mut Option<Event<T>>,
+        source: EventSource,
+        force: bool,
+    ) -> Result<(), SendError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(SendError::Closed);
+        }
+        let Some(ty) = slot.as_ref().map(|x| x.get_type()) else {
+            return Ok(());
+        };
+        let policy = state.policies.get(&ty).copied().unwrap_or_default();
+        if policy == OverflowPolicy::Coalesce
+            && let Some(queued) = state.events.iter_mut().rev().find(|x| x.1.get_type() == ty)
+        {
+            *queued = (source, slot.take().unwrap());
+            return Ok(());
+        }
+        if !force && state.events.len() >= self.capacity {
+            match policy {
+                OverflowPolicy::Wait | OverflowPolicy::Coalesce => {
+                    return Err(SendError::Full);
+                }
+                OverflowPolicy::DropNewest => {
+                    slot.take();
+                    return Ok(());
+                }
+                OverflowPolicy::DropOldest => {
+                    let Some(idx) = state.events.iter().position(|x| x.1.get_type() == ty) else {
+                        slot.take();
+                        return Ok(());
+                    };
+                    state.events.remove(idx);
+                }
+            }
+        }
+        state.events.push_back((source, slot.take().unwrap()));
+        drop(state);
+        self.readable.notify_one();
         Ok(())
     }
 
-    pub async fn run(&mut self, setup: impl AsyncFn(EventSync<T>)) {
-        setup(EventSync::new(self.handler.sender.clone())).await;
+    fn pop(&self) -> Result<(EventSource, Event<T>), TryRecvError> {
+        let mut state = self.state.lock().unwrap();
+        match state.events.pop_front() {
+            Some(ev) => {
+                drop(state);
+                self.writable.notify_waiters();
+                Ok(ev)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+/*
+* the sending half of an EventHandler's queue
+* */
+pub struct EventSender<T: ThreadSafeIsh> {
+    queue: Arc<EventQueue<T>>,
+    source: EventSource,
+}
+
+impl<T: ThreadSafeIsh> Clone for EventSender<T> {
+    fn clone(&self) -> Self {
+        self.with_source(self.source)
+    }
+}
+
+impl<T: ThreadSafeIsh> Drop for EventSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.queue.readable.notify_one();
+        }
+    }
+}
+
+impl<T: ThreadSafeIsh> EventSender<T> {
+    /*
+     * a sender for the same queue whose events are traced as coming from source
+     * */
+    pub fn with_source(&self, source: EventSource) -> Self {
+        self.queue.state.lock().unwrap().senders += 1;
+        Self {
+            queue: self.queue.clone(),
+            source,
+        }
+    }
+
+    pub fn source(&self) -> EventSource {
+        self.source
+    }
+
+    pub fn try_send(&self, ev: Event<T>) -> Result<(), SendError> {
+        self.queue.push(&mut Some(ev), self.source, false)
+    }
+
+    /*
+     * waits for room instead of reporting Full
+     * */
+    pub async fn send(&self, ev: Event<T>) -> Result<(), SendError> {
+        let mut slot = Some(ev);
         loop {
-            let res = self.handle_events().await;
-            if res.is_err() {
-                todo!();
+            let notified = self.queue.writable.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            match self.queue.push(&mut slot, self.source, false) {
+                Err(SendError::Full) => notified.await,
+                out => return out,
             }
-            let res = self.handle_services().await;
-            if res.is_err() {
-                todo!()
+        }
+    }
+
+    /*
+     * ignores the capacity, for events the handler sends itself while it is the one that would
+     * have to make room
+     * */
+    fn force_send(&self, ev: Event<T>) -> Result<(), SendError> {
+        self.queue.push(&mut Some(ev), self.source, true)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity
+    }
+
+    pub fn node_id(&self) -> u64 {
+        self.queue.node
+    }
+
+    fn take_reply(&self, id: MessageId) -> Option<ReplySlot> {
+        self.queue.replies.lock().unwrap().remove(&id)
+    }
+
+    pub fn next_origin(&self) -> Origin {
+        Origin {
+            node: self.queue.node,
+            seq: self
+                .queue
+                .origin_seq
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.state.lock().unwrap().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct EventReceiver<T: ThreadSafeIsh> {
+    queue: Arc<EventQueue<T>>,
+}
+
+impl<T: ThreadSafeIsh> Drop for EventReceiver<T> {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().closed = true;
+        self.queue.writable.notify_waiters();
+    }
+}
+
+impl<T: ThreadSafeIsh> EventReceiver<T> {
+    pub fn try_recv(&mut self) -> Result<Event<T>, TryRecvError> {
+        self.queue.pop().map(|x| x.1)
+    }
+
+    pub async fn recv(&mut self) -> Option<Event<T>> {
+        self.recv_sourced().await.map(|x| x.1)
+    }
+
+    pub fn try_recv_sourced(&mut self) -> Result<(EventSource, Event<T>), TryRecvError> {
+        self.queue.pop()
+    }
+
+    /*
+     * None once every sender is gone and the queue is drained
+     * */
+    pub async fn recv_sourced(&mut self) -> Option<(EventSource, Event<T>)> {
+        loop {
+            let notified = self.queue.readable.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            match self.queue.pop() {
+                Ok(ev) => return Some(ev),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => notified.await,
             }
         }
     }
+
+    pub fn set_overflow_policy(&mut self, event_type: EventType, policy: OverflowPolicy) {
+        self.queue
+            .state
+            .lock()
+            .unwrap()
+            .policies
+            .insert(event_type, policy);
+    }
+}
+
+/*
+* random per queue so handlers in different processes don't collide, never 0
+* */
+fn new_node_id() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|x| x.as_nanos())
+            .unwrap_or(0),
+    );
+    hasher.finish().max(1)
+}
+
+/*
+* a capacity of usize::MAX makes the queue unbounded
+* */
+pub fn event_queue<T: ThreadSafeIsh>(capacity: usize) -> (EventSender<T>, EventReceiver<T>) {
+    let queue = Arc::new(EventQueue {
+        state: Mutex::new(QueueState {
+            events: VecDeque::new(),
+            policies: BTreeMap::new(),
+            senders: 1,
+            closed: false,
+        }),
+        capacity,
+        node: new_node_id(),
+        origin_seq: AtomicU64::new(0),
+        replies: Mutex::new(BTreeMap::new()),
+        names: Mutex::new(BTreeMap::new()),
+        readable: tokio::sync::Notify::new(),
+        writable: tokio::sync::Notify::new(),
+    });
+    (
+        EventSender {
+            queue: queue.clone(),
+            source: EventSource::External,
+        },
+        EventReceiver { queue },
+    )
+}
+
+/*
+* one event as the handler saw it, offset is measured from when the tracer was created and
+* payload is only filled in for UserDefined events
+* */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceRecord<T> {
+    pub seq: u64,
+    pub offset: Duration,
+    pub unix_ms: u64,
+    pub source: EventSource,
+    pub event_type: EventType,
+    pub payload: Option<T>,
+}
+
+pub trait EventTracer<T: ThreadSafeIsh>: ThreadSafeIsh {
+    fn record(&mut self, source: EventSource, event: &Event<T>) -> Throws<()>;
+}
+
+struct TraceClock {
+    start: std::time::Instant,
+    seq: u64,
+}
+
+impl TraceClock {
+    fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+            seq: 0,
+        }
+    }
+
+    fn stamp<P, T: ThreadSafeIsh>(
+        &mut self,
+        source: EventSource,
+        event: &Event<T>,
+        payload: Option<P>,
+    ) -> TraceRecord<P> {
+        self.seq += 1;
+        let unix_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or(0);
+        TraceRecord {
+            seq: self.seq,
+            offset: self.start.elapsed(),
+            unix_ms,
+            source,
+            event_type: event.get_type(),
+            payload,
+        }
+    }
+}
+
+/*
+* keeps the last capacity records in memory, clones share the same buffer so one can be handed to
+* set_tracer and the other kept for reading
+* */
+pub struct TraceRing<T> {
+    records: Arc<Mutex<VecDeque<TraceRecord<T>>>>,
+    clock: Arc<Mutex<TraceClock>>,
+    capacity: usize,
+}
+
+impl<T> Clone for TraceRing<T> {
+    fn clone(&self) -> Self {
+        Self {
+            records: self.records.clone(),
+            clock: self.clock.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T: Clone> TraceRing<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(VecDeque::new())),
+            clock: Arc::new(Mutex::new(TraceClock::new())),
+            capacity,
+        }
+    }
+
+    pub fn records(&self) -> Vec<TraceRecord<T>> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+}
+
+impl<T: ThreadSafeIsh + Clone> EventTracer<T> for TraceRing<T> {
+    fn record(&mut self, source: EventSource, event: &Event<T>) -> Throws<()> {
+        let payload = match event {
+            Event::UserDefined(t) => Some(t.clone()),
+            _ => None,
+        };
+        let record = self.clock.lock().unwrap().stamp(source, event, payload);
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+        Ok(())
+    }
+}
+
+/*
+* writes every record as a line of json, read it back with read_trace
+* */
+pub struct TraceFile {
+    out: std::io::BufWriter<std::fs::File>,
+    clock: TraceClock,
+}
+
+impl TraceFile {
+    pub fn create(path: impl AsRef<std::path::Path>) -> Throws<Self> {
+        Ok(Self {
+            out: std::io::BufWriter::new(std::fs::File::create(path)?),
+            clock: TraceClock::new(),
+        })
+    }
+}
+
+impl<T: ThreadSafeIsh + Serialize> EventTracer<T> for TraceFile {
+    fn record(&mut self, source: EventSource, event: &Event<T>) -> Throws<()> {
+        use std::io::Write;
+        let payload = match event {
+            Event::UserDefined(t) => Some(t),
+            _ => None,
+        };
+        let record = self.clock.stamp(source, event, payload);
+        serde_json::to_writer(&mut self.out, &record)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+pub fn read_trace<T: serde::de::DeserializeOwned>(
+    path: impl AsRef<std::path::Path>,
+) -> Throws<Vec<TraceRecord<T>>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut out = Vec::new();
+    for line in text.lines().filter(|x| !x.trim().is_empty()) {
+        out.push(serde_json::from_str(line)?);
+    }
+    Ok(out)
 }
 
 pub struct EventSync<T: ThreadSafeIsh> {
-    sender: Option<Sender<Event<T>>>,
+    sender: Option<EventSender<T>>,
 }
 impl<T: ThreadSafeIsh> Clone for EventSync<T> {
     fn clone(&self) -> Self {
@@ -657,35 +2299,201 @@ impl<T: ThreadSafeIsh> Clone for EventSync<T> {
     }
 }
 impl<T: ThreadSafeIsh> EventSync<T> {
-    pub fn new(sender: Sender<Event<T>>) -> Self {
+    pub fn new(sender: EventSender<T>) -> Self {
         Self {
             sender: Some(sender),
         }
     }
 
-    pub fn new_event(&self, ev: T) -> Throws<()> {
+    pub fn new_event(&self, ev: T) -> Throws<()> {
+        self.try_send(ev)?;
+        Ok(())
+    }
+
+    pub fn new_event_global(&self, ev: Event<T>) -> Throws<()> {
+        self.try_send_global(ev)?;
+        Ok(())
+    }
+
+    pub fn try_send(&self, ev: T) -> Result<(), SendError> {
+        self.try_send_global(Event::UserDefined(ev))
+    }
+
+    pub fn try_send_global(&self, ev: Event<T>) -> Result<(), SendError> {
+        self.sender.as_ref().unwrap().try_send(ev)
+    }
+
+    /*
+     * waits for the handler to make room instead of failing with SendError::Full, don't await
+     * these from inside a subscriber or service since the handler is the one that has to drain
+     * the queue
+     * */
+    pub async fn send(&self, ev: T) -> Throws<()> {
+        self.send_global(Event::UserDefined(ev)).await
+    }
+
+    pub async fn send_global(&self, ev: Event<T>) -> Throws<()> {
+        self.sender.as_ref().unwrap().send(ev).await?;
+        Ok(())
+    }
+
+    /*
+     * delivers ev as a UserDefined event once delay has passed, counted from when the handler
+     * picks the timer up so it follows the handler's clock
+     * */
+    pub fn schedule_after(&self, delay: Duration, ev: T) -> Throws<TimerId> {
+        let mut ev = Some(ev);
+        self.schedule_timer(
+            Instant::now() + delay,
+            Some(delay),
+            None,
+            Box::new(move || ev.take()),
+        )
+    }
+
+    pub fn schedule_at(&self, at: Instant, ev: T) -> Throws<TimerId> {
+        let mut ev = Some(ev);
+        self.schedule_timer(at, None, None, Box::new(move || ev.take()))
+    }
+
+    /*
+     * delivers a clone of ev every period, starting one period from now
+     * */
+    pub fn schedule_every(&self, every: Duration, ev: T) -> Throws<TimerId>
+    where
+        T: Clone,
+    {
+        self.schedule_timer(
+            Instant::now() + every,
+            Some(every),
+            Some(every),
+            Box::new(move || Some(ev.clone())),
+        )
+    }
+
+    fn schedule_timer(
+        &self,
+        at: Instant,
+        after: Option<Duration>,
+        every: Option<Duration>,
+        next: TimerFn<T>,
+    ) -> Throws<TimerId> {
+        let id = TimerId::next();
+        self.try_send_global(Event::ScheduleTimer(Timer {
+            id,
+            at,
+            after,
+            every,
+            next,
+        }))?;
+        Ok(id)
+    }
+
+    /*
+     * asks the handler to shut down, EventHandler::run returns once it is done
+     * */
+    pub fn shutdown(&self) -> Throws<()> {
+        self.try_send_global(Event::Shutdown)?;
+        Ok(())
+    }
+
+    /*
+     * identifies this handler to bridged peers, see Event::Remote
+     * */
+    pub fn node_id(&self) -> u64 {
+        self.sender.as_ref().unwrap().node_id()
+    }
+
+    pub fn next_origin(&self) -> Origin {
+        self.sender.as_ref().unwrap().next_origin()
+    }
+
+    /*
+     * cancelling a timer that already fired or doesn't exist does nothing
+     * */
+    pub fn cancel_timer(&self, id: TimerId) -> Throws<()> {
+        self.try_send_global(Event::CancelTimer { id })?;
+        Ok(())
+    }
+
+    /*
+     * sends ev to whichever subscriber claims it with wants_request and waits for its reply, the
+     * event is queued before this returns so requests keep the order they were made in
+     * */
+    pub fn request<R: Any + Send>(&self, ev: T) -> impl Future<Output = Throws<R>> + use<T, R> {
+        self.request_timeout(ev, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    pub fn request_timeout<R: Any + Send>(
+        &self,
+        ev: T,
+        timeout: Duration,
+    ) -> impl Future<Output = Throws<R>> + use<T, R> {
+        let (slot, reader) = WriteOnce::create();
+        let sent = self.try_send_global(Event::Request {
+            event: ev,
+            reply: Reply { slot },
+        });
+        async move {
+            sent?;
+            let Ok(reply) = tokio::time::timeout(timeout, reader.read()).await else {
+                throw!("request timed out");
+            };
+            let Ok(value) = reply??.downcast::<R>() else {
+                throw!("reply had the wrong type");
+            };
+            Ok(*value)
+        }
+    }
+
+    pub fn create_new_subscriber<Sub: EventSub<T> + 'static>(
+        &self,
+        subscriber: Sub,
+    ) -> Result<(), Box<dyn Error>> {
+        let bx = Box::new(subscriber) as Box<dyn EventSub<T>>;
+        self.sender
+            .as_ref()
+            .unwrap()
+            .try_send(Event::CreateSubscriber(bx, false))?;
+        Ok(())
+    }
+
+    /*
+     * like create_new_subscriber but the factory is kept so SupervisionPolicy::Restart can build
+     * a fresh one after a fault
+     * */
+    pub fn create_restartable_subscriber<Sub: EventSub<T> + 'static>(
+        &self,
+        factory: impl Fn() -> Sub + Send + Sync + 'static,
+    ) -> Throws<()> {
+        let factory: SubscriberFactory<T> = Arc::new(move || Box::new(factory()));
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::UserDefined(ev))
-            .unwrap();
+            .try_send(Event::CreateRestartableSubscriber(factory))?;
         Ok(())
     }
 
-    pub fn new_event_global(&self, ev: Event<T>) -> Throws<()> {
-        self.sender.as_ref().unwrap().send(ev).unwrap();
+    pub fn create_restartable_service<Serv: Service<T> + 'static>(
+        &self,
+        factory: impl Fn() -> Serv + Send + Sync + 'static,
+    ) -> Throws<()> {
+        let factory: ServiceFactory<T> = Arc::new(move || Box::new(factory()));
+        self.sender
+            .as_ref()
+            .unwrap()
+            .try_send(Event::CreateRestartableService(factory))?;
         Ok(())
     }
 
-    pub fn create_new_subscriber<Sub: EventSub<T> + 'static>(
-        &self,
-        subscriber: Sub,
-    ) -> Result<(), Box<dyn Error>> {
-        let bx = Box::new(subscriber) as Box<dyn EventSub<T>>;
+    /*
+     * makes the service update on the next spin of the loop even if its tick isn't due
+     * */
+    pub fn wake_service(&self, id: ServiceId) -> Throws<()> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::CreateSubscriber(bx, false))?;
+            .try_send(Event::WakeService { id })?;
         Ok(())
     }
 
@@ -697,7 +2505,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::CreateService(bx, false))?;
+            .try_send(Event::CreateService(bx, false))?;
         Ok(())
     }
 
@@ -709,7 +2517,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::CreateService(bx, true))?;
+            .try_send(Event::CreateService(bx, true))?;
         Ok(())
     }
 
@@ -717,7 +2525,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::DestroySubscriber { id })?;
+            .try_send(Event::DestroySubscriber { id })?;
         Ok(())
     }
 
@@ -725,7 +2533,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::DestroyService { id })?;
+            .try_send(Event::DestroyService { id })?;
         Ok(())
     }
 
@@ -733,7 +2541,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::TcpConnection { stream, id })?;
+            .try_send(Event::TcpConnection { stream, id })?;
         Ok(())
     }
 
@@ -741,39 +2549,64 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::NotifyNewTcpConnection { id })?;
+            .try_send(Event::NotifyNewTcpConnection { id })?;
         Ok(())
     }
 
-    pub fn tcp_disconnect(&self, id: TcpConnectionId) -> Throws<()> {
+    /*
+     * hands an error from a daemon to the handler so it reaches the error sink and goes out as
+     * Event::SubscriberFaulted, daemon faults never escalate
+     * */
+    pub fn report_fault(&self, error: Exception) -> Throws<()> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::NotifyNewTcpConnection { id })?;
+            .force_send(Event::DaemonFaulted(error))?;
         Ok(())
     }
 
-    pub fn net_input(&self, id: TcpConnectionId, data: Arc<[u8]>) -> Throws<()> {
+    pub fn tcp_disconnect(&self, id: TcpConnectionId) -> Throws<()> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::NetInput { id, data })?;
+            .try_send(Event::TcpDisconnect { id })?;
         Ok(())
     }
 
-    pub fn http_request(&self, id: TcpConnectionId, request: HTTPRequest) -> Throws<()> {
+    pub fn net_input(&self, id: TcpConnectionId, data: Arc<[u8]>) -> Throws<()> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::HttpRequest { id, request })?;
+            .try_send(Event::NetInput { id, data })?;
         Ok(())
     }
 
-    pub fn http_response(&self, id: TcpConnectionId, response: HTTPResponse) -> Throws<()> {
+    pub fn http_request(
+        &self,
+        id: TcpConnectionId,
+        request_id: HttpRequestId,
+        request: HTTPRequest,
+    ) -> Throws<()> {
+        self.sender.as_ref().unwrap().try_send(Event::HttpRequest {
+            id,
+            request_id,
+            request,
+        })?;
+        Ok(())
+    }
+
+    /*
+     * answers the Event::HttpRequest carrying request_id, answers to requests that already timed
+     * out are dropped by the connection
+     * */
+    pub fn respond_http(&self, request_id: HttpRequestId, response: HTTPResponse) -> Throws<()> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::HttpResponse { id, response })?;
+            .try_send(Event::HttpResponse {
+                request_id,
+                response,
+            })?;
         Ok(())
     }
 
@@ -781,14 +2614,34 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::NetOutput { id, data })?;
+            .try_send(Event::NetOutput { id, data })?;
+        Ok(())
+    }
+    pub fn websocket_send(&self, id: TcpConnectionId, message: WsMessage) -> Throws<()> {
+        self.sender
+            .as_ref()
+            .unwrap()
+            .try_send(Event::WebSocketSend { id, message })?;
         Ok(())
     }
+
+    pub fn websocket_send_text(&self, id: TcpConnectionId, text: &str) -> Throws<()> {
+        self.websocket_send(id, WsMessage::Text(text.into()))
+    }
+
+    pub fn websocket_send_binary(&self, id: TcpConnectionId, data: Arc<[u8]>) -> Throws<()> {
+        self.websocket_send(id, WsMessage::Binary(data))
+    }
+
+    pub fn websocket_close(&self, id: TcpConnectionId) -> Throws<()> {
+        self.websocket_send(id, WsMessage::Close(Some((1000, "".into()))))
+    }
+
     pub fn create_daemon(&self, daemon: Box<dyn Daemon>, id: DaemonId) -> Throws<()> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::CreateDaemon { daemon, id })?;
+            .try_send(Event::CreateDaemon { daemon, id })?;
         Ok(())
     }
 
@@ -796,7 +2649,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::RequestDaemonKill { id: to_kill })?;
+            .try_send(Event::RequestDaemonKill { id: to_kill })?;
         Ok(())
     }
 
@@ -804,7 +2657,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::RequestConnectionKill { id: to_kill })?;
+            .try_send(Event::RequestConnectionKill { id: to_kill })?;
         Ok(())
     }
 
@@ -812,7 +2665,7 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::RequestServiceKill { id: to_kill })?;
+            .try_send(Event::RequestServiceKill { id: to_kill })?;
         Ok(())
     }
 
@@ -820,15 +2673,90 @@ impl<T: ThreadSafeIsh> EventSync<T> {
         self.sender
             .as_ref()
             .unwrap()
-            .send(Event::RequestSubscriberKill { id: to_kill })?;
+            .try_send(Event::RequestSubscriberKill { id: to_kill })?;
         Ok(())
     }
 
     pub fn new_message(&self, msg: Message) -> Throws<()> {
-        self.sender.as_ref().unwrap().send(Event::Message(msg))?;
+        self.sender
+            .as_ref()
+            .unwrap()
+            .try_send(Event::Message(msg))?;
+        Ok(())
+    }
+
+    /*
+     * the id is handed out right away, messages sent to it queue up behind the object itself.
+     * it carries this handler's node_id so bridged messages for it only run here
+     * */
+    pub fn spawn_object(&self, object: Box<dyn Object>) -> Throws<ObjectId> {
+        let id = ObjectId::on_node(self.node_id());
+        self.try_send_global(Event::AllocObject { id, object })?;
+        Ok(id)
+    }
+
+    /*
+     * like spawn_object but the object can also be found through object_id(name) until it is
+     * freed
+     * */
+    pub fn spawn_named_object(&self, name: &str, object: Box<dyn Object>) -> Throws<ObjectId> {
+        let queue = &self.sender.as_ref().unwrap().queue;
+        let mut names = queue.names.lock().unwrap();
+        if names.contains_key(name) {
+            throw!(format!("an object named {} already exists", name));
+        }
+        let id = self.spawn_object(object)?;
+        names.insert(name.into(), id);
+        Ok(id)
+    }
+
+    pub fn object_id(&self, name: &str) -> Option<ObjectId> {
+        let queue = &self.sender.as_ref().unwrap().queue;
+        queue.names.lock().unwrap().get(name).copied()
+    }
+
+    pub fn free_object(&self, id: ObjectId) -> Throws<()> {
+        self.try_send_global(Event::FreeObject { id })?;
         Ok(())
     }
 
+    /*
+     * sends msg and waits for the Event::MessageReply with its id, decoding the result with the
+     * message's encoding
+     * */
+    pub fn call_object<R: DeserializeOwned>(
+        &self,
+        msg: Message,
+    ) -> impl Future<Output = Throws<R>> + use<T, R> {
+        self.call_object_timeout(msg, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    pub fn call_object_timeout<R: DeserializeOwned>(
+        &self,
+        mut msg: Message,
+        timeout: Duration,
+    ) -> impl Future<Output = Throws<R>> + use<T, R> {
+        let sender = self.sender.as_ref().unwrap();
+        let queue = sender.queue.clone();
+        let (id, encoding) = (msg.id, msg.encoding);
+        msg.reply = true;
+        msg.reply_to = sender.node_id();
+        let (slot, reader) = WriteOnce::create();
+        queue.replies.lock().unwrap().insert(id, slot);
+        let sent = sender.try_send(Event::Message(msg));
+        async move {
+            let result = match sent {
+                Ok(()) => tokio::time::timeout(timeout, reader.read()).await,
+                Err(e) => Ok(Err(e.into())),
+            };
+            queue.replies.lock().unwrap().remove(&id);
+            let Ok(result) = result else {
+                throw!("call timed out");
+            };
+            encoding.decode(&result??)
+        }
+    }
+
     pub fn invalid() -> Self {
         Self { sender: None }
     }
@@ -867,9 +2795,13 @@ impl<T> BPipe<T> {
         Ok(())
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.done.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
     pub fn recieve(&self) -> Throws<Option<T>> {
         if self.done.load(std::sync::atomic::Ordering::Relaxed) {
-            todo!()
+            return Err("done".into());
         }
         let mut recieving = self.recieving.lock().unwrap();
         Ok(recieving.pop_front())
@@ -1005,6 +2937,21 @@ impl<T: ThreadSafeIsh + Clone> EventForwarder<T> {
     }
 }
 
+impl<T: ThreadSafeIsh> EventForwarder<T> {
+    /*
+     * once the other end of a pipe is dropped there is nobody left to forward to, so the
+     * forwarder asks to be removed instead of failing on every send
+     * */
+    fn is_orphaned(&self) -> bool {
+        let orphaned = self.pipe.as_ref().is_some_and(|x| x.is_closed())
+            || self.event_pipe.as_ref().is_some_and(|x| x.is_closed());
+        if orphaned && self.self_id.is_valid() {
+            _ = self.sender.kill_subscriber(self.self_id);
+        }
+        orphaned
+    }
+}
+
 #[async_trait]
 impl<T: ThreadSafeIsh + Clone> EventSub<T> for EventForwarder<T> {
     async fn on_create(&mut self, self_id: SubId, sender: EventSync<T>) {
@@ -1025,7 +2972,7 @@ impl<T: ThreadSafeIsh + Clone> EventSub<T> for EventForwarder<T> {
     }
 
     async fn wants_global_event(&self, event: &Event<T>) -> Throws<EventRequest> {
-        if self.event_pipe.is_none() {
+        if self.event_pipe.is_none() || self.is_orphaned() {
             return Ok(EventRequest::None);
         }
         if event.is_clonable() {
@@ -1040,6 +2987,9 @@ impl<T: ThreadSafeIsh + Clone> EventSub<T> for EventForwarder<T> {
     }
 
     async fn wants_event(&self, event: &T) -> Throws<EventRequest> {
+        if self.pipe.is_none() || self.is_orphaned() {
+            return Ok(EventRequest::None);
+        }
         if (self.should_forward)(event) {
             Ok(EventRequest::Shared)
         } else {
@@ -1110,83 +3060,952 @@ impl IdAllocatorInternal {
     }
 }
 
+/*
+* an event the harness saw go through the handler, event is None for the ones that can't be cloned
+* (creating subscribers, services, daemons and so on)
+* */
+pub struct Captured<T: ThreadSafeIsh> {
+    pub source: EventSource,
+    pub event_type: EventType,
+    pub event: Option<Event<T>>,
+}
+
+struct Capture<T: ThreadSafeIsh> {
+    events: Arc<Mutex<Vec<Captured<T>>>>,
+}
+
+impl<T: ThreadSafeIsh + Clone> EventTracer<T> for Capture<T> {
+    fn record(&mut self, source: EventSource, event: &Event<T>) -> Throws<()> {
+        self.events.lock().unwrap().push(Captured {
+            source,
+            event_type: event.get_type(),
+            event: event.try_clone(),
+        });
+        Ok(())
+    }
+}
+
+pub const HARNESS_STEP_LIMIT: usize = 100_000;
+
+/*
+* drives an EventHandler by hand for tests, nothing runs until step, run_until_idle or advance is
+* called and time only moves through advance. every event that goes through the handler is
+* captured with its source
+* */
+pub struct EventHarness<T: ThreadSafeIsh + Clone> {
+    handler: EventHandler<T>,
+    sync: EventSync<T>,
+    now: Arc<Mutex<Instant>>,
+    captured: Arc<Mutex<Vec<Captured<T>>>>,
+}
+
+impl<T: ThreadSafeIsh + Clone> Default for EventHarness<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ThreadSafeIsh + Clone> EventHarness<T> {
+    pub fn new() -> Self {
+        let (sender, mut handler) = EventHandler::with_capacity(usize::MAX);
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        handler.handler.clock = Clock::Virtual(now.clone());
+        handler.set_tracer(Capture {
+            events: captured.clone(),
+        });
+        Self {
+            handler,
+            sync: EventSync::new(sender),
+            now,
+            captured,
+        }
+    }
+
+    pub fn sync(&self) -> EventSync<T> {
+        self.sync.clone()
+    }
+
+    pub fn handler(&mut self) -> &mut EventHandler<T> {
+        &mut self.handler
+    }
+
+    pub fn inject(&self, ev: T) -> Throws<()> {
+        self.sync.new_event(ev)
+    }
+
+    pub fn inject_global(&self, ev: Event<T>) -> Throws<()> {
+        self.sync.new_event_global(ev)
+    }
+
+    /*
+     * fires due timers and handles at most one queued event, false when there was nothing
+     * */
+    pub async fn step(&mut self) -> Throws<bool> {
+        self.handler.handler.fire_timers()?;
+        let Ok((source, ev)) = self.handler.channel.try_recv_sourced() else {
+            return Ok(false);
+        };
+        self.handler.handler.dispatch(source, ev).await?;
+        self.handler.supervise().await?;
+        Ok(true)
+    }
+
+    /*
+     * handles events and updates due services until neither has anything left to do, returns
+     * how many rounds that took
+     * */
+    pub async fn run_until_idle(&mut self) -> Throws<usize> {
+        let mut rounds = 0;
+        loop {
+            let stepped = self.step().await?;
+            if !stepped && self.handler.update_services().await? == 0 {
+                self.handler.supervise().await?;
+                return Ok(rounds);
+            }
+            self.handler.supervise().await?;
+            rounds += 1;
+            if rounds > HARNESS_STEP_LIMIT {
+                throw!("the handler never went idle");
+            }
+        }
+    }
+
+    /*
+     * moves virtual time forward stopping at every timer and service tick on the way, so a timer
+     * every 10ms fires ten times over 100ms
+     * */
+    pub async fn advance(&mut self, by: Duration) -> Throws<()> {
+        let target = self.now() + by;
+        self.run_until_idle().await?;
+        while let Some(next) = self.handler.handler.next_deadline() {
+            if next > target {
+                break;
+            }
+            {
+                let mut now = self.now.lock().unwrap();
+                *now = (*now).max(next);
+            }
+            self.run_until_idle().await?;
+        }
+        *self.now.lock().unwrap() = target;
+        self.run_until_idle().await?;
+        Ok(())
+    }
+
+    pub fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    pub fn captured(&self) -> std::sync::MutexGuard<'_, Vec<Captured<T>>> {
+        self.captured.lock().unwrap()
+    }
+
+    pub fn clear_captured(&self) {
+        self.captured.lock().unwrap().clear();
+    }
+
+    /*
+     * every UserDefined event the handler dispatched, in order
+     * */
+    pub fn delivered(&self) -> Vec<T> {
+        self.user_events(|_| true)
+    }
+
+    /*
+     * UserDefined events sent by subscribers and services rather than injected
+     * */
+    pub fn emitted(&self) -> Vec<T> {
+        self.user_events(|x| matches!(x, EventSource::Subscriber(_) | EventSource::Service(_)))
+    }
+
+    pub fn emitted_by(&self, source: EventSource) -> Vec<T> {
+        self.user_events(|x| x == source)
+    }
+
+    fn user_events(&self, filter: impl Fn(EventSource) -> bool) -> Vec<T> {
+        let mut out = Vec::new();
+        for c in self.captured().iter() {
+            if let Some(Event::UserDefined(t)) = &c.event
+                && filter(c.source)
+            {
+                out.push(t.clone());
+            }
+        }
+        out
+    }
+
+    pub fn count(&self, event_type: EventType) -> usize {
+        self.captured()
+            .iter()
+            .filter(|x| x.event_type == event_type)
+            .count()
+    }
+
+    /*
+     * object calls that went through the handler
+     * */
+    pub fn messages(&self) -> Vec<Message> {
+        let mut out = Vec::new();
+        for c in self.captured().iter() {
+            if let Some(Event::Message(m)) = &c.event {
+                out.push(m.clone());
+            }
+        }
+        out
+    }
+
+    pub fn subscribers(&self) -> Vec<SubId> {
+        self.handler.handler.subscribers.keys().copied().collect()
+    }
+
+    pub fn services(&self) -> Vec<ServiceId> {
+        self.handler.handler.services.keys().copied().collect()
+    }
+
+    pub fn objects(&self) -> Vec<ObjectId> {
+        self.handler.handler.objects.keys().copied().collect()
+    }
+}
+
+struct OnceSlot<T> {
+    value: Option<T>,
+    waker: Option<std::task::Waker>,
+    handles: usize,
+}
+
+/*
+* a one shot cell shared by two handles, reading fails once the other handle is dropped without
+* writing
+* */
 pub struct WriteOnce<T> {
-    v: Arc<Mutex<Option<T>>>,
+    v: Arc<Mutex<OnceSlot<T>>>,
+}
+impl<T> Drop for WriteOnce<T> {
+    fn drop(&mut self) {
+        let mut slot = lock_slot(&self.v);
+        slot.handles -= 1;
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn lock_slot<T>(v: &Mutex<OnceSlot<T>>) -> std::sync::MutexGuard<'_, OnceSlot<T>> {
+    match v.lock() {
+        Ok(value) => value,
+        Err(value) => value.into_inner(),
+    }
 }
+
 impl<T> WriteOnce<T> {
     pub fn create() -> (Self, Self) {
-        let vout = Arc::new(Mutex::new(None));
+        let vout = Arc::new(Mutex::new(OnceSlot {
+            value: None,
+            waker: None,
+            handles: 2,
+        }));
         let a = Self { v: vout.clone() };
         let b = Self { v: vout };
         (a, b)
     }
-    pub fn read(&self) -> impl Future<Output = Result<T, Box<dyn Error>>> {
+    pub fn read(&self) -> impl Future<Output = Throws<T>> + use<T> {
         struct Out<T> {
-            v: Arc<Mutex<Option<T>>>,
+            v: Arc<Mutex<OnceSlot<T>>>,
         }
         impl<T> Future for Out<T> {
-            type Output = Result<T, Box<dyn Error>>;
+            type Output = Throws<T>;
             fn poll(
                 self: std::pin::Pin<&mut Self>,
-                _cx: &mut std::task::Context<'_>,
+                cx: &mut std::task::Context<'_>,
             ) -> std::task::Poll<Self::Output> {
-                let lock = self.v.try_lock();
-                match lock {
-                    Ok(mut t) => {
-                        if let Some(m) = t.take() {
-                            std::task::Poll::Ready(Ok(m))
-                        } else {
-                            std::task::Poll::Pending
-                        }
-                    }
-                    Err(e) => match e {
-                        std::sync::TryLockError::Poisoned(poison_error) => {
-                            let mut lck = poison_error.into_inner();
-                            if let Some(m) = lck.take() {
-                                std::task::Poll::Ready(Ok(m))
-                            } else {
-                                std::task::Poll::Pending
-                            }
-                        }
-                        std::sync::TryLockError::WouldBlock => std::task::Poll::Pending,
-                    },
+                let mut slot = lock_slot(&self.v);
+                if let Some(m) = slot.value.take() {
+                    return std::task::Poll::Ready(Ok(m));
                 }
+                if slot.handles < 2 {
+                    return std::task::Poll::Ready(Err("dropped without a value".into()));
+                }
+                slot.waker = Some(cx.waker().clone());
+                std::task::Poll::Pending
             }
         }
-        let out = Out { v: self.v.clone() };
-        out
+        Out { v: self.v.clone() }
     }
 
     pub fn write(self, v: T) {
-        let lock = self.v.lock();
-        let mut value = match lock {
-            Ok(value) => value,
-            Err(value) => value.into_inner(),
-        };
-        *value = Some(v);
+        let mut slot = lock_slot(&self.v);
+        slot.value = Some(v);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
     }
 
-    pub fn try_read(&self) -> Result<Option<T>, Box<dyn Error>> {
-        let lock = self.v.try_lock();
-        match lock {
-            Ok(mut t) => {
-                if let Some(m) = t.take() {
-                    Ok(Some(m))
-                } else {
-                    Ok(None)
-                }
+    pub fn try_read(&self) -> Throws<Option<T>> {
+        let mut slot = lock_slot(&self.v);
+        if let Some(m) = slot.value.take() {
+            return Ok(Some(m));
+        }
+        if slot.handles < 2 {
+            throw!("dropped without a value");
+        }
+        Ok(None)
+    }
+}
+
+#[tokio::test]
+async fn supervision_tests() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    struct Flaky {
+        seen: Arc<AtomicUsize>,
+    }
+    #[async_trait]
+    impl EventSub<&'static str> for Flaky {
+        async fn on_create(&mut self, _: SubId, _: EventSync<&'static str>) {}
+        async fn wants_event(&self, _: &&'static str) -> Throws<EventRequest> {
+            Ok(EventRequest::Shared)
+        }
+        async fn on_event(&mut self, event: &&'static str) -> Throws<()> {
+            if *event == "fail" {
+                return Err("flaky subscriber failed".into());
             }
-            Err(e) => match e {
-                std::sync::TryLockError::Poisoned(poison_error) => {
-                    let mut lck = poison_error.into_inner();
-                    if let Some(m) = lck.take() {
-                        Ok(Some(m))
-                    } else {
-                        Ok(None)
-                    }
-                }
-                std::sync::TryLockError::WouldBlock => Ok(None),
-            },
+            self.seen.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+    struct Watcher {
+        reports: Arc<Mutex<Vec<FaultReport>>>,
+    }
+    #[async_trait]
+    impl EventSub<&'static str> for Watcher {
+        async fn on_create(&mut self, _: SubId, _: EventSync<&'static str>) {}
+        async fn on_event(&mut self, _: &&'static str) -> Throws<()> {
+            Ok(())
+        }
+        async fn wants_global_event(&self, event: &Event<&'static str>) -> Throws<EventRequest> {
+            Ok(match event {
+                Event::SubscriberFaulted(_) => EventRequest::Shared,
+                _ => EventRequest::None,
+            })
+        }
+        async fn on_global_event<'a>(&'a self, event: &Event<&'static str>) -> Throws<()> {
+            if let Event::SubscriberFaulted(report) = event {
+                self.reports.lock().unwrap().push(report.clone());
+            }
+            Ok(())
+        }
+    }
+    let (sender, mut handler) = EventHandler::<&'static str>::new();
+    let sync = EventSync::new(sender);
+    let created = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::new(AtomicUsize::new(0));
+    let sunk = Arc::new(AtomicUsize::new(0));
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    handler.set_supervision_policy(SupervisionPolicy::Restart);
+    let sunk2 = sunk.clone();
+    handler.set_error_sink(move |_, _| {
+        sunk2.fetch_add(1, Ordering::Relaxed);
+    });
+    let (c, s) = (created.clone(), seen.clone());
+    sync.create_restartable_subscriber(move || {
+        c.fetch_add(1, Ordering::Relaxed);
+        Flaky { seen: s.clone() }
+    })
+    .unwrap();
+    sync.create_new_subscriber(Watcher {
+        reports: reports.clone(),
+    })
+    .unwrap();
+    sync.new_event("fail").unwrap();
+    sync.new_event("ok").unwrap();
+    handler.handle_events().await.unwrap();
+    handler.supervise().await.unwrap();
+    handler.handle_events().await.unwrap();
+    assert_eq!(created.load(Ordering::Relaxed), 2);
+    assert_eq!(seen.load(Ordering::Relaxed), 1);
+    assert_eq!(sunk.load(Ordering::Relaxed), 1);
+    {
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].action, FaultAction::Restarted);
+        assert_eq!(reports[0].error.as_ref(), "flaky subscriber failed");
+    }
+    handler.set_supervision_policy(SupervisionPolicy::Escalate);
+    sync.new_event("fail").unwrap();
+    handler.handle_events().await.unwrap();
+    assert!(handler.supervise().await.is_err());
+    sync.report_fault("daemon failed".into()).unwrap();
+    handler.handle_events().await.unwrap();
+    handler.supervise().await.unwrap();
+    handler.handle_events().await.unwrap();
+    assert_eq!(sunk.load(Ordering::Relaxed), 3);
+    let reports = reports.lock().unwrap();
+    let last = reports.last().unwrap();
+    assert_eq!(last.source, FaultSource::Daemon);
+    assert_eq!(last.action, FaultAction::Continued);
+}
+
+#[tokio::test]
+async fn service_restart_tests() {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    struct Flaky {
+        ids: Arc<Mutex<Vec<ServiceId>>>,
+        fail: Arc<AtomicBool>,
+        updates: Arc<AtomicUsize>,
+    }
+    #[async_trait]
+    impl Service<()> for Flaky {
+        async fn create(&mut self, id: ServiceId, _: EventSync<()>) {
+            self.ids.lock().unwrap().push(id);
+        }
+        async fn update(&mut self) -> Throws<()> {
+            if self.fail.swap(false, Ordering::Relaxed) {
+                throw!("flaky service failed");
+            }
+            self.updates.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+        fn tick_interval(&self) -> Option<Duration> {
+            None
+        }
+    }
+    let ids = Arc::new(Mutex::new(Vec::new()));
+    let updates = Arc::new(AtomicUsize::new(0));
+    let (sender, mut handler) = EventHandler::<()>::new();
+    handler.set_supervision_policy(SupervisionPolicy::Restart);
+    handler.set_error_sink(|_, _| {});
+    let sync = EventSync::new(sender);
+    sync.create_new_service(Flaky {
+        ids: ids.clone(),
+        fail: Arc::new(AtomicBool::new(false)),
+        updates: updates.clone(),
+    })
+    .unwrap();
+    let (i, u) = (ids.clone(), updates.clone());
+    let fail = Arc::new(AtomicBool::new(true));
+    sync.create_restartable_service(move || Flaky {
+        ids: i.clone(),
+        fail: fail.clone(),
+        updates: u.clone(),
+    })
+    .unwrap();
+    handler.handle_events().await.unwrap();
+    handler.handle_services().await.unwrap();
+    assert_eq!(updates.load(Ordering::Relaxed), 1);
+    handler.supervise().await.unwrap();
+    handler.handle_services().await.unwrap();
+    assert_eq!(updates.load(Ordering::Relaxed), 2);
+    let ids = ids.lock().unwrap().clone();
+    assert_eq!(ids.len(), 3);
+    assert_ne!(ids[0], ids[1]);
+    assert_eq!(ids[1], ids[2]);
+}
+
+#[tokio::test]
+async fn service_wake_tests() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    struct Lazy {
+        id: Arc<Mutex<Option<ServiceId>>>,
+        updates: Arc<AtomicUsize>,
+    }
+    #[async_trait]
+    impl Service<()> for Lazy {
+        async fn create(&mut self, id: ServiceId, _: EventSync<()>) {
+            *self.id.lock().unwrap() = Some(id);
+        }
+        async fn update(&mut self) -> Throws<()> {
+            self.updates.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+        fn tick_interval(&self) -> Option<Duration> {
+            None
         }
     }
+    let id = Arc::new(Mutex::new(None));
+    let updates = Arc::new(AtomicUsize::new(0));
+    let (sender, mut handler) = EventHandler::<()>::new();
+    let sync = EventSync::new(sender);
+    sync.create_new_service(Lazy {
+        id: id.clone(),
+        updates: updates.clone(),
+    })
+    .unwrap();
+    handler.handle_events().await.unwrap();
+    handler.handle_services().await.unwrap();
+    handler.handle_services().await.unwrap();
+    assert_eq!(updates.load(Ordering::Relaxed), 1);
+    let id = id.lock().unwrap().unwrap();
+    sync.wake_service(id).unwrap();
+    handler.handle_events().await.unwrap();
+    handler.handle_services().await.unwrap();
+    handler.handle_services().await.unwrap();
+    assert_eq!(updates.load(Ordering::Relaxed), 2);
+    tokio::spawn(async move {
+        let _ = handler.run(async |_| {}).await;
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(updates.load(Ordering::Relaxed), 2);
+    sync.wake_service(id).unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(updates.load(Ordering::Relaxed), 3);
+}
+
+#[tokio::test]
+async fn topic_subscription_tests() {
+    struct Recorder {
+        name: &'static str,
+        subscriptions: Subscriptions,
+        owned: bool,
+        log: Arc<Mutex<Vec<(&'static str, &'static str)>>>,
+    }
+    #[async_trait]
+    impl EventSub<&'static str> for Recorder {
+        async fn on_create(&mut self, _: SubId, _: EventSync<&'static str>) {}
+        fn subscriptions(&self) -> Subscriptions {
+            self.subscriptions.clone()
+        }
+        async fn wants_event(&self, _: &&'static str) -> Throws<EventRequest> {
+            Ok(if self.owned {
+                EventRequest::Owned
+            } else {
+                EventRequest::Shared
+            })
+        }
+        async fn on_event(&mut self, event: &&'static str) -> Throws<()> {
+            self.log.lock().unwrap().push((self.name, *event));
+            Ok(())
+        }
+        async fn on_event_owned(&mut self, event: &'static str) -> Throws<()> {
+            self.log.lock().unwrap().push((self.name, event));
+            Ok(())
+        }
+    }
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (sender, mut handler) = EventHandler::<&'static str>::new();
+    handler.set_topic_key(|x: &&'static str| x.split(':').next().unwrap_or("").to_string());
+    let sync = EventSync::new(sender);
+    let recorder = |name, subscriptions, owned| Recorder {
+        name,
+        subscriptions,
+        owned,
+        log: log.clone(),
+    };
+    sync.create_new_subscriber(recorder("jobs_a", Subscriptions::none().topic("job"), true))
+        .unwrap();
+    sync.create_new_subscriber(recorder("chat", Subscriptions::none().topic("chat"), false))
+        .unwrap();
+    sync.create_new_subscriber(recorder("jobs_b", Subscriptions::none().topic("job"), true))
+        .unwrap();
+    sync.create_new_subscriber(recorder("all", Subscriptions::all(), false))
+        .unwrap();
+    handler.handle_events().await.unwrap();
+    sync.new_event("chat:hi").unwrap();
+    sync.new_event("job:1").unwrap();
+    sync.new_event("other").unwrap();
+    handler.handle_events().await.unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            ("chat", "chat:hi"),
+            ("all", "chat:hi"),
+            ("jobs_a", "job:1"),
+            ("all", "other"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn bounded_queue_tests() {
+    let (sender, mut handler) = EventHandler::<u32>::with_capacity(2);
+    let sync = EventSync::new(sender.clone());
+    sync.try_send(1).unwrap();
+    sync.try_send(2).unwrap();
+    assert_eq!(sync.try_send(3), Err(SendError::Full));
+    let click = |x| Event::MouseInput {
+        input_mouse_input: MouseInput::LeftClick {
+            x,
+            y: 0,
+            is_currently_down: true,
+        },
+    };
+    handler.set_overflow_policy(EventType::UserDefined, OverflowPolicy::DropNewest);
+    sync.try_send(3).unwrap();
+    assert_eq!(sender.len(), 2);
+    handler.set_overflow_policy(EventType::MouseInput, OverflowPolicy::Coalesce);
+    assert!(sync.try_send_global(click(1)).is_err());
+    let waiting = {
+        let sync = sync.clone();
+        tokio::spawn(async move { sync.send_global(click(2)).await })
+    };
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert!(!waiting.is_finished());
+    assert!(matches!(
+        handler.channel.try_recv(),
+        Ok(Event::UserDefined(1))
+    ));
+    waiting.await.unwrap().unwrap();
+    sync.try_send_global(click(3)).unwrap();
+    assert_eq!(sender.len(), 2);
+    assert!(matches!(
+        handler.channel.try_recv(),
+        Ok(Event::UserDefined(2))
+    ));
+    assert!(matches!(
+        handler.channel.try_recv(),
+        Ok(Event::MouseInput {
+            input_mouse_input: MouseInput::LeftClick { x: 3, .. }
+        })
+    ));
+    assert!(matches!(
+        handler.channel.try_recv(),
+        Err(TryRecvError::Empty)
+    ));
+    sync.try_send_global(click(4)).unwrap();
+    sync.try_send_global(click(5)).unwrap();
+    handler.handle_events().await.unwrap();
+    assert!(sender.is_empty());
+}
+
+#[tokio::test]
+async fn request_reply_tests() {
+    struct Doubler {
+        parked: Vec<Reply>,
+    }
+    #[async_trait]
+    impl EventSub<u32> for Doubler {
+        async fn on_create(&mut self, _: SubId, _: EventSync<u32>) {}
+        async fn on_event(&mut self, _: &u32) -> Throws<()> {
+            Ok(())
+        }
+        async fn wants_request(&self, event: &u32) -> Throws<bool> {
+            Ok(*event < 100)
+        }
+        async fn on_request(&mut self, event: u32, reply: Reply) -> Throws<()> {
+            match event {
+                0 => self.parked.push(reply),
+                1 => {}
+                _ => reply.send(event * 2),
+            }
+            Ok(())
+        }
+    }
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    let sync = EventSync::new(sender);
+    sync.create_new_subscriber(Doubler { parked: Vec::new() })
+        .unwrap();
+    tokio::spawn(async move {
+        let _ = handler.run(async |_| {}).await;
+    });
+    assert_eq!(sync.request::<u32>(21).await.unwrap(), 42);
+    let err = sync.request::<u32>(500).await.unwrap_err();
+    assert_eq!(err.error.to_string(), "no subscriber claimed the request");
+    let err = sync.request::<String>(21).await.unwrap_err();
+    assert_eq!(err.error.to_string(), "reply had the wrong type");
+    let err = sync.request::<u32>(1).await.unwrap_err();
+    assert_eq!(err.error.to_string(), "dropped without a value");
+    let err = sync
+        .request_timeout::<u32>(0, Duration::from_millis(20))
+        .await
+        .unwrap_err();
+    assert_eq!(err.error.to_string(), "request timed out");
+}
+
+#[tokio::test]
+async fn timer_tests() {
+    struct Recorder {
+        seen: Arc<Mutex<Vec<u32>>>,
+    }
+    #[async_trait]
+    impl EventSub<u32> for Recorder {
+        async fn on_create(&mut self, _: SubId, _: EventSync<u32>) {}
+        async fn wants_event(&self, _: &u32) -> Throws<EventRequest> {
+            Ok(EventRequest::Shared)
+        }
+        async fn on_event(&mut self, event: &u32) -> Throws<()> {
+            self.seen.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    let sync = EventSync::new(sender);
+    sync.create_new_subscriber(Recorder { seen: seen.clone() })
+        .unwrap();
+    tokio::spawn(async move {
+        let _ = handler.run(async |_| {}).await;
+    });
+    let count = |x| seen.lock().unwrap().iter().filter(|y| **y == x).count();
+    sync.schedule_after(Duration::from_millis(60), 1).unwrap();
+    let every = sync.schedule_every(Duration::from_millis(20), 2).unwrap();
+    let cancelled = sync.schedule_after(Duration::from_millis(40), 3).unwrap();
+    sync.cancel_timer(cancelled).unwrap();
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    assert_eq!(count(1), 0);
+    tokio::time::sleep(Duration::from_millis(70)).await;
+    assert_eq!(count(1), 1);
+    assert_eq!(count(3), 0);
+    assert!(count(2) >= 3);
+    sync.cancel_timer(every).unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let ticks = count(2);
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(count(2), ticks);
+}
+
+#[tokio::test]
+async fn shutdown_tests() {
+    use std::sync::atomic::Ordering;
+    struct Ticker {
+        stopped: Arc<AtomicBool>,
+        stubborn: bool,
+    }
+    #[async_trait]
+    impl Daemon for Ticker {
+        async fn run(&mut self) {
+            loop {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+        async fn on_shutdown(&mut self) {
+            if self.stubborn {
+                std::future::pending::<()>().await;
+            }
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+    struct Closer {
+        closed: Arc<AtomicBool>,
+    }
+    #[async_trait]
+    impl EventSub<()> for Closer {
+        async fn on_create(&mut self, _: SubId, _: EventSync<()>) {}
+        async fn on_event(&mut self, _: &()) -> Throws<()> {
+            Ok(())
+        }
+        async fn on_shutdown(&mut self) -> Throws<()> {
+            self.closed.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+    let stopped = Arc::new(AtomicBool::new(false));
+    let closed = Arc::new(AtomicBool::new(false));
+    let (sender, mut handler) = EventHandler::<()>::new();
+    let sync = EventSync::new(sender);
+    sync.create_new_subscriber(Closer {
+        closed: closed.clone(),
+    })
+    .unwrap();
+    let ticker = Ticker {
+        stopped: stopped.clone(),
+        stubborn: false,
+    };
+    sync.create_daemon(Box::new(ticker), DaemonId::alloc())
+        .unwrap();
+    let running = tokio::spawn(async move { handler.run(async |_| {}).await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    sync.shutdown().unwrap();
+    tokio::time::timeout(Duration::from_secs(1), running)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(stopped.load(Ordering::Relaxed));
+    assert!(closed.load(Ordering::Relaxed));
+
+    let (sender, mut handler) = EventHandler::<()>::new();
+    handler.set_shutdown_timeout(Duration::from_millis(50));
+    let sync = EventSync::new(sender);
+    let stubborn = Ticker {
+        stopped: Arc::new(AtomicBool::new(false)),
+        stubborn: true,
+    };
+    sync.create_daemon(Box::new(stubborn), DaemonId::alloc())
+        .unwrap();
+    sync.shutdown().unwrap();
+    tokio::time::timeout(Duration::from_secs(1), handler.run(async |_| {}))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn trace_replay_tests() {
+    struct Echo {
+        sync: Option<EventSync<u32>>,
+        seen: Arc<Mutex<Vec<u32>>>,
+    }
+    #[async_trait]
+    impl EventSub<u32> for Echo {
+        async fn on_create(&mut self, _: SubId, sync: EventSync<u32>) {
+            self.sync = Some(sync);
+        }
+        async fn wants_event(&self, _: &u32) -> Throws<EventRequest> {
+            Ok(EventRequest::Shared)
+        }
+        async fn on_event(&mut self, event: &u32) -> Throws<()> {
+            self.seen.lock().unwrap().push(*event);
+            if *event < 100 {
+                self.sync.as_ref().unwrap().new_event(event + 100)?;
+            }
+            Ok(())
+        }
+    }
+    let path = std::env::temp_dir().join(format!("rtils_trace_{}.jsonl", std::process::id()));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let ring = TraceRing::new(3);
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    handler.set_tracer(ring.clone());
+    let sync = EventSync::new(sender);
+    sync.create_new_subscriber(Echo {
+        sync: None,
+        seen: seen.clone(),
+    })
+    .unwrap();
+    sync.new_event(1).unwrap();
+    sync.new_event(2).unwrap();
+    handler.handle_events().await.unwrap();
+    let records = ring.records();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].payload, Some(2));
+    assert_eq!(records[0].source, EventSource::External);
+    assert_eq!(records[1].payload, Some(101));
+    assert!(matches!(records[1].source, EventSource::Subscriber(_)));
+    assert!(records[1].seq < records[2].seq);
+
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    handler.set_tracer(TraceFile::create(&path).unwrap());
+    let sync = EventSync::new(sender);
+    sync.create_new_subscriber(Echo {
+        sync: None,
+        seen: seen.clone(),
+    })
+    .unwrap();
+    seen.lock().unwrap().clear();
+    sync.new_event(7).unwrap();
+    sync.new_event(8).unwrap();
+    handler.handle_events().await.unwrap();
+    let recorded = seen.lock().unwrap().clone();
+    let records = read_trace::<u32>(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(records[0].event_type, EventType::CreateSubscriber);
+    assert_eq!(records.len(), 5);
+
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    let replayed = Arc::new(Mutex::new(Vec::new()));
+    EventSync::new(sender)
+        .create_new_subscriber(Echo {
+            sync: None,
+            seen: replayed.clone(),
+        })
+        .unwrap();
+    handler.handle_events().await.unwrap();
+    handler.replay(records).await.unwrap();
+    assert_eq!(*replayed.lock().unwrap(), recorded);
+}
+
+#[tokio::test]
+async fn harness_tests() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    struct Delayer {
+        sync: Option<EventSync<u32>>,
+    }
+    #[async_trait]
+    impl EventSub<u32> for Delayer {
+        async fn on_create(&mut self, _: SubId, sync: EventSync<u32>) {
+            self.sync = Some(sync);
+        }
+        async fn wants_event(&self, event: &u32) -> Throws<EventRequest> {
+            Ok(if *event < 1000 {
+                EventRequest::Shared
+            } else {
+                EventRequest::None
+            })
+        }
+        async fn on_event(&mut self, event: &u32) -> Throws<()> {
+            let sync = self.sync.as_ref().unwrap();
+            sync.new_event(event + 1000)?;
+            sync.schedule_after(Duration::from_millis(100), event + 2000)?;
+            Ok(())
+        }
+    }
+    struct Ticker {
+        ticks: Arc<AtomicUsize>,
+    }
+    #[async_trait]
+    impl Service<u32> for Ticker {
+        async fn create(&mut self, _: ServiceId, _: EventSync<u32>) {}
+        async fn update(&mut self) -> Throws<()> {
+            self.ticks.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+        fn tick_interval(&self) -> Option<Duration> {
+            Some(Duration::from_millis(50))
+        }
+    }
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let mut harness = EventHarness::<u32>::new();
+    let sync = harness.sync();
+    sync.create_new_subscriber(Delayer { sync: None }).unwrap();
+    sync.create_new_service(Ticker {
+        ticks: ticks.clone(),
+    })
+    .unwrap();
+    assert!(harness.step().await.unwrap());
+    assert_eq!(harness.subscribers().len(), 1);
+    assert!(harness.services().is_empty());
+    harness.run_until_idle().await.unwrap();
+    assert_eq!(harness.services().len(), 1);
+    assert_eq!(ticks.load(Ordering::Relaxed), 1);
+    harness.inject(1).unwrap();
+    harness.run_until_idle().await.unwrap();
+    assert_eq!(harness.delivered(), vec![1, 1001]);
+    assert_eq!(harness.emitted(), vec![1001]);
+    assert_eq!(harness.count(EventType::ScheduleTimer), 1);
+    harness.advance(Duration::from_millis(99)).await.unwrap();
+    assert_eq!(harness.delivered(), vec![1, 1001]);
+    assert_eq!(ticks.load(Ordering::Relaxed), 2);
+    harness.advance(Duration::from_millis(1)).await.unwrap();
+    assert_eq!(harness.delivered(), vec![1, 1001, 2001]);
+    harness.advance(Duration::from_millis(400)).await.unwrap();
+    assert_eq!(ticks.load(Ordering::Relaxed), 11);
+    harness
+        .sync()
+        .new_message(Message::new(ObjectId::invalid(), "ping", &[(); 0]).unwrap())
+        .unwrap();
+    harness.run_until_idle().await.unwrap();
+    assert_eq!(harness.messages()[0].to_call.as_ref(), "ping");
+}
+
+#[tokio::test]
+async fn object_lifecycle_tests() {
+    use crate::msg::TestObject;
+    let mut harness = EventHarness::<u32>::new();
+    let sync = harness.sync();
+    let id = sync
+        .spawn_named_object("adder", Box::new(TestObject {}))
+        .unwrap();
+    assert_eq!(sync.object_id("adder"), Some(id));
+    assert!(
+        sync.spawn_named_object("adder", Box::new(TestObject {}))
+            .is_err()
+    );
+    let reply = sync.call_object::<i32>(Message::new(id, "add", &(2, 3)).unwrap());
+    harness.run_until_idle().await.unwrap();
+    assert_eq!(harness.objects(), vec![id]);
+    assert_eq!(reply.await.unwrap(), 5);
+    sync.free_object(id).unwrap();
+    harness.run_until_idle().await.unwrap();
+    assert!(harness.objects().is_empty());
+    assert_eq!(sync.object_id("adder"), None);
+    let reply = sync.call_object::<i32>(Message::new(id, "add", &(2, 3)).unwrap());
+    harness.run_until_idle().await.unwrap();
+    let err = reply.await.unwrap_err().error_as::<CallError>().unwrap();
+    assert_eq!(*err, CallError::NoSuchObject { id });
+    assert_eq!(harness.count(EventType::DeadLetter), 1);
+    let next = sync.spawn_object(Box::new(TestObject {})).unwrap();
+    assert_ne!(next, id);
+    sync.new_message(Message::new(id, "add", &(2, 3)).unwrap())
+        .unwrap();
+    harness.run_until_idle().await.unwrap();
+    assert_eq!(harness.count(EventType::DeadLetter), 2);
 }
@@ -1,5 +1,13 @@
 use std::{backtrace, fmt::Display, str::FromStr};
 
+/*
+* lets the code #[object] generates name ::rtils from inside this crate as well
+* */
+extern crate self as rtils;
+
+pub use rtils_macros::object;
+
+pub mod bridge;
 pub mod database;
 pub mod events;
 pub mod msg;
@@ -114,10 +122,6 @@ pub fn dyn_scanf(input: &str, format: &str, args: &mut [&mut dyn CopyFromStr]) -
     }
     return true;
 }
-mod rtils {
-    #[allow(unused)]
-    pub use crate::*;
-}
 #[macro_export]
 macro_rules! sscanf {
     ($input:expr, $fmt:literal) => {
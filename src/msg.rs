@@ -1,40 +1,321 @@
-use std::{collections::HashMap, sync::{Arc, LazyLock}};
+use std::{collections::HashMap, fmt::Display, sync::{Arc, LazyLock, atomic::{AtomicBool, AtomicU64}}};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de::{DeserializeOwned, IgnoredAny, SeqAccess, Visitor}};
 
-use crate::{DEFINE_ID_WRAPPER, events::IDS, Throws, events::{ThreadSafeIsh, EventSync}};
+use crate::{Exception, Throws, events::{ThreadSafeIsh, EventSync}};
 use concat_idents::concat_idents;
 
-DEFINE_ID_WRAPPER!(ObjectId);
+/*
+* comes from a counter and is never handed out twice, so a message to a freed object ends up as a
+* dead letter instead of reaching whatever object was spawned after it. node is the
+* EventSync::node_id of the handler that owns the object, only that handler runs messages sent to
+* it and bridges carry them there. ids with node 0 are run by whichever handler gets the message
+* */
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ObjectId {
+    node: u64,
+    inner: u64,
+}
+impl ObjectId {
+    pub fn invalid() -> Self {
+        Self { node: 0, inner: 0 }
+    }
+    pub fn inner(&self) -> u64 {
+        self.inner
+    }
+    pub fn node(&self) -> u64 {
+        self.node
+    }
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self {
+            node: 0,
+            inner: NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+    pub fn alloc() -> Self {
+        Self::next()
+    }
+    pub fn on_node(node: u64) -> Self {
+        Self { node, ..Self::next() }
+    }
+}
+/*
+* ties a Message to the Event::MessageReply carrying its result, never reused
+* */
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct MessageId {
+    inner: u64,
+}
+impl MessageId {
+    pub fn invalid() -> Self {
+        Self { inner: 0 }
+    }
+    pub fn inner(&self) -> u64 {
+        self.inner
+    }
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self {
+            inner: NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+}
+/*
+* how a Message payload and its reply are serialized, Json is there for reading messages while
+* debugging
+* */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Encoding{
+    #[default]
+    MessagePack,
+    Json,
+}
+static JSON_BY_DEFAULT:AtomicBool = AtomicBool::new(false);
+impl Encoding{
+    /*
+     * what Message::new and the define_method! wrappers use, MessagePack unless set_default
+     * changed it
+     * */
+    pub fn current()->Self{
+        if JSON_BY_DEFAULT.load(std::sync::atomic::Ordering::Relaxed){
+            Encoding::Json
+        }else{
+            Encoding::MessagePack
+        }
+    }
+    pub fn set_default(encoding:Encoding){
+        JSON_BY_DEFAULT.store(encoding == Encoding::Json, std::sync::atomic::Ordering::Relaxed);
+    }
+    pub fn encode<V:Serialize+?Sized>(&self, value:&V)->Throws<Vec<u8>>{
+        Ok(match self{
+            Encoding::MessagePack => rmp_serde::to_vec(value)?,
+            Encoding::Json => serde_json::to_vec_pretty(value)?,
+        })
+    }
+    pub fn decode<V:DeserializeOwned>(&self, bytes:&[u8])->Throws<V>{
+        Ok(match self{
+            Encoding::MessagePack => rmp_serde::from_slice(bytes)?,
+            Encoding::Json => serde_json::from_slice(bytes)?,
+        })
+    }
+    /*
+     * hands the payload to visitor as a sequence so each argument can be decoded and reported on
+     * by itself
+     * */
+    pub fn decode_seq<'de, V:Visitor<'de>>(&self, bytes:&'de [u8], visitor:V)->Throws<V::Value>{
+        Ok(match self{
+            Encoding::MessagePack => rmp_serde::Deserializer::from_read_ref(bytes).deserialize_seq(visitor)?,
+            Encoding::Json => serde_json::Deserializer::from_slice(bytes).deserialize_seq(visitor)?,
+        })
+    }
+}
+/*
+* a method call on an object, payload holds the arguments as one sequence in the given encoding.
+* when reply is set the handler sends the return value back as Event::MessageReply, encoded the
+* same way and addressed to the node in reply_to
+* */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message{
     pub target_id:ObjectId,
     pub to_call:Arc<str>,
-    pub message:Arc<[String]>
+    pub encoding:Encoding,
+    pub payload:Arc<[u8]>,
+    pub id:MessageId,
+    pub reply:bool,
+    pub reply_to:u64,
+}
+impl Message{
+    /*
+     * args should serialize as a sequence, a tuple of the arguments or [(); 0] for none
+     * */
+    pub fn new(target_id:ObjectId, to_call:&str, args:&impl Serialize)->Throws<Self>{
+        Self::with_encoding(target_id, to_call, Encoding::current(), args)
+    }
+    pub fn with_encoding(target_id:ObjectId, to_call:&str, encoding:Encoding, args:&impl Serialize)->Throws<Self>{
+        Ok(Self{
+            target_id,
+            to_call:to_call.into(),
+            encoding,
+            payload:encoding.encode(args)?.into(),
+            id:MessageId::next(),
+            reply:false,
+            reply_to:0,
+        })
+    }
+}
+/*
+* why an Object could not run a message, goes back to the caller in Event::MessageReply
+* */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CallError{
+    UnknownMethod{method:Arc<str>},
+    Arity{method:Arc<str>, expected:usize, got:usize},
+    Decode{method:Arc<str>, index:usize, error:String},
+    NoSuchObject{id:ObjectId},
+    Failed(String),
+}
+impl Display for CallError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            CallError::UnknownMethod { method } => write!(f, "no method named {}", method),
+            CallError::Arity { method, expected, got } => write!(f, "{} takes {} arguments but got {}", method, expected, got),
+            CallError::Decode { method, index, error } => write!(f, "argument {} of {} did not decode:{}", index, method, error),
+            CallError::NoSuchObject { id } => write!(f, "no object with id {}", id.inner()),
+            CallError::Failed(error) => write!(f, "{}", error),
+        }
+    }
+}
+impl std::error::Error for CallError{}
+/*
+* anything that isn't already a CallError, like a return value that failed to encode, becomes
+* Failed
+* */
+impl From<Exception> for CallError{
+    fn from(value: Exception) -> Self {
+        match value.error.downcast::<CallError>(){
+            Ok(e) => *e,
+            Err(e) => CallError::Failed(e.to_string()),
+        }
+    }
+}
+/*
+* what an Object says about itself through describe, types are spelled the way the source spells
+* them
+* */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArgSchema{
+    pub name:Arc<str>,
+    pub ty:Arc<str>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MethodSchema{
+    pub name:Arc<str>,
+    pub args:Vec<ArgSchema>,
+    pub returns:Arc<str>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectSchema{
+    pub name:Arc<str>,
+    pub methods:Vec<MethodSchema>,
+}
+impl ObjectSchema{
+    pub fn method(&self, name:&str)->Option<&MethodSchema>{
+        self.methods.iter().find(|x| x.name.as_ref() == name)
+    }
 }
 pub trait Object:ThreadSafeIsh+{
-    fn call(&mut self, message:Message);
+    /*
+     * runs the method and returns its result serialized like the arguments, a bad method name or
+     * bad arguments come back as a CallError
+     * */
+    fn call(&mut self, message:Message)->Throws<Vec<u8>>;
     fn can_accept(&self, message:Message)->bool;
+    /*
+     * objects written by hand that don't fill this in describe no methods
+     * */
+    fn describe(&self)->ObjectSchema{
+        ObjectSchema{
+            name:std::any::type_name::<Self>().into(),
+            methods:Vec::new(),
+        }
+    }
 }
-#[macro_export]
-macro_rules! des_arg {
-    ($x:ty,$args:expr) => {
-        serde_json::from_str($args.next().unwrap()).unwrap()
+/*
+* a tuple of method arguments read one at a time out of a payload sequence, see decode_args
+* */
+pub trait ArgList:Sized{
+    const LEN:usize;
+    fn visit<'de, A:SeqAccess<'de>>(seq:&mut A, method:&str)->Result<Result<Self, CallError>, A::Error>;
+}
+macro_rules! impl_arg_list{
+    ($($t:ident),*) => {
+        impl<$($t:DeserializeOwned),*> ArgList for ($($t,)*){
+            const LEN:usize = 0 $(+ {stringify!($t); 1})*;
+            #[allow(unused_mut, unused_variables, unused_assignments)]
+            fn visit<'de, S:SeqAccess<'de>>(seq:&mut S, method:&str)->Result<Result<Self, CallError>, S::Error>{
+                let mut index = 0;
+                Ok(Ok(($(
+                    match seq.next_element::<$t>(){
+                        Ok(Some(v)) => {
+                            index += 1;
+                            v
+                        }
+                        Ok(None) => return Ok(Err(CallError::Arity{method:method.into(), expected:Self::LEN, got:index})),
+                        Err(e) => return Ok(Err(CallError::Decode{method:method.into(), index, error:e.to_string()})),
+                    },
+                )*)))
+            }
+        }
     };
 }
-
+impl_arg_list!();
+impl_arg_list!(TA);
+impl_arg_list!(TA, TB);
+impl_arg_list!(TA, TB, TC);
+impl_arg_list!(TA, TB, TC, TD);
+impl_arg_list!(TA, TB, TC, TD, TE);
+impl_arg_list!(TA, TB, TC, TD, TE, TF);
+impl_arg_list!(TA, TB, TC, TD, TE, TF, TG);
+impl_arg_list!(TA, TB, TC, TD, TE, TF, TG, TH);
+impl_arg_list!(TA, TB, TC, TD, TE, TF, TG, TH, TI);
+impl_arg_list!(TA, TB, TC, TD, TE, TF, TG, TH, TI, TJ);
+impl_arg_list!(TA, TB, TC, TD, TE, TF, TG, TH, TI, TJ, TK);
+impl_arg_list!(TA, TB, TC, TD, TE, TF, TG, TH, TI, TJ, TK, TL);
+/*
+* the arguments of message as the tuple L, the define_method! wrappers decode with this too
+* */
+pub fn decode_args<L:ArgList>(message:&Message)->Throws<L>{
+    struct Args<'a, L>(&'a str, std::marker::PhantomData<L>);
+    impl<'de, L:ArgList> Visitor<'de> for Args<'_, L>{
+        type Value = Result<L, CallError>;
+        fn expecting(&self, f:&mut std::fmt::Formatter)->std::fmt::Result{
+            write!(f, "the arguments of {}", self.0)
+        }
+        fn visit_seq<A:SeqAccess<'de>>(self, mut seq:A)->Result<Self::Value, A::Error>{
+            let args = match L::visit(&mut seq, self.0)?{
+                Ok(args) => args,
+                Err(e) => return Ok(Err(e)),
+            };
+            let mut got = L::LEN;
+            while seq.next_element::<IgnoredAny>()?.is_some(){
+                got += 1;
+            }
+            if got != L::LEN{
+                return Ok(Err(CallError::Arity{method:self.0.into(), expected:L::LEN, got}));
+            }
+            Ok(Ok(args))
+        }
+    }
+    let method = message.to_call.as_ref();
+    match message.encoding.decode_seq(&message.payload, Args(method, std::marker::PhantomData)){
+        Ok(args) => Ok(args?),
+        Err(e) => Err(CallError::Decode{method:method.into(), index:0, error:e.error.to_string()})?,
+    }
+}
+#[macro_export]
+macro_rules! method_return {
+    () => { () };
+    ($r:ty) => { $r };
+}
+#[macro_export]
+macro_rules! method_return_name {
+    () => { "()" };
+    ($r:ty) => { stringify!($r) };
+}
+#[macro_export]
+macro_rules! encode_args {
+    () => { &[(); 0] };
+    ($($y:ident),+) => { &($(&$y,)+) };
+}
 #[macro_export]
 macro_rules! make_callable_function{
-    ($lower_case_name:ident,$name:ident,  $( $x:ty),*) => {
+    ($lower_case_name:ident,$name:ident,  $( $y:ident:$x:ty),*) => {
         concat_idents!(rname = $lower_case_name, _, $name, _ ,wrapper {
-            pub fn rname(ptr:&mut Self,args:Vec<String>){
-                let mut args = args.iter();
-                ptr.$name(  
-                    $(
-                        des_arg!($x, args),
-                    )*
-                );
-                assert!(args.next().is_none());
+            pub fn rname(ptr:&mut Self,message:&Message)->Throws<Vec<u8>>{
+                let ($($y,)*) = $crate::msg::decode_args::<($($x,)*)>(message)?;
+                let out = ptr.$name($($y),*);
+                message.encoding.encode(&out)
             }
         });
     };
@@ -42,37 +323,23 @@ macro_rules! make_callable_function{
 
 #[macro_export]
 macro_rules! make_method{
-    ($lower_case_name:ident,$self_name:ident, $name:ident,$( $y:ident:$x:ty),*) => {
+    ($lower_case_name:ident,$self_name:ident, $name:ident,($( $y:ident:$x:ty),*)$(,$r:ty)?) => {
         concat_idents!(rname = $lower_case_name, _, $name, _ ,wrapper{
-            pub fn rname(object:ObjectId,events:EventSync<impl ThreadSafeIsh>,$( $y:$x),*)->Throws<()>{
-                let mut args = Vec::new();
-                $(
-                    args.push(serde_json::to_string_pretty(&$y)?);
-                )*
+            pub fn rname(object:ObjectId,events:EventSync<impl ThreadSafeIsh>,$( $y:$x),*)->impl Future<Output = Throws<method_return!($($r)?)>>{
                 let name = stringify!($name);
-                let msg = Message{
-                    message:args.into(), 
-                    to_call:name.into(), 
-                    target_id:object
-                };
-                events.new_message(msg)?;
-                Ok(())
+                let out = Message::new(object, name, encode_args!($($y),*)).map(|msg| events.call_object(msg));
+                async move{
+                    out?.await
+                }
         }
         });
         concat_idents!(direct_wrapper_name = $lower_case_name, _, $name, _ ,wrapper,_direct {
-            pub fn direct_wrapper_name(ptr:&mut $self_name, $( $y:$x),*)->Throws<()>{
-                let mut args = Vec::new();
-                $(
-                    args.push(serde_json::to_string_pretty(&$y)?);
-                )*
+            pub fn direct_wrapper_name(ptr:&mut $self_name, $( $y:$x),*)->Throws<method_return!($($r)?)>{
                 let name = stringify!($name);
-                let msg = Message{
-                    message:args.into(), 
-                    to_call:name.into(), 
-                    target_id:ObjectId::invalid(),
-                };
-                ptr.call(msg);
-                Ok(())
+                let msg = Message::new(ObjectId::invalid(), name, encode_args!($($y),*))?;
+                let encoding = msg.encoding;
+                let out = ptr.call(msg)?;
+                encoding.decode(&out)
             }
         });
     };
@@ -81,11 +348,11 @@ macro_rules! make_method{
 #[macro_export]
 macro_rules! method_table {
     ($lower_case_name:ident,$self_name:ident,($($name:ident),*)) => {
-        pub fn create_method_table()->std::collections::HashMap<String,fn(&mut $self_name, Vec<String>)>{
+        pub fn create_method_table()->std::collections::HashMap<String,fn(&mut $self_name, &Message)->Throws<Vec<u8>>>{
             let mut out = std::collections::HashMap::new();
             $(
                 concat_idents!(wrapper_name = $lower_case_name, _, $name, _ ,wrapper{
-                out.insert(stringify!($name).to_string(), Self::wrapper_name as fn(&mut $self_name, Vec<String>));
+                out.insert(stringify!($name).to_string(), Self::wrapper_name as fn(&mut $self_name, &Message)->Throws<Vec<u8>>);
                 });
             )*
             out
@@ -96,10 +363,10 @@ macro_rules! method_table {
 
 #[macro_export]
 macro_rules! define_method{
-    ($self_name:ident,$lower_case_name:ident,$((fn $name:ident ($($y:ident:$x:ty),*))),*) => {
+    ($self_name:ident,$lower_case_name:ident,$((fn $name:ident ($($y:ident:$x:ty),*)$(->$r:ty)?)),*) => {
         impl $self_name{
             $(
-                make_callable_function!($lower_case_name,$name, $($x),*);
+                make_callable_function!($lower_case_name,$name, $($y:$x),*);
             )*
             method_table!(
                 $lower_case_name,
@@ -109,17 +376,34 @@ macro_rules! define_method{
             );
         }
         $(
-            make_method!($lower_case_name,$self_name, $name, $($y:$x),*);
+            make_method!($lower_case_name,$self_name, $name, ($($y:$x),*)$(,$r)?);
         )*
         impl Object for $self_name{
-            fn call(&mut self, message:Message) {
-                static TABLE:LazyLock<HashMap<String,fn(&mut $self_name, args:Vec<String>)>> = LazyLock::new(||{$self_name::create_method_table()});
-                (TABLE.get(message.to_call.as_ref()).unwrap())(self,message.message.clone().to_vec())
+            fn call(&mut self, message:Message)->Throws<Vec<u8>> {
+                static TABLE:LazyLock<HashMap<String,fn(&mut $self_name, &Message)->Throws<Vec<u8>>>> = LazyLock::new(||{$self_name::create_method_table()});
+                let Some(method) = TABLE.get(message.to_call.as_ref()) else {
+                    Err($crate::msg::CallError::UnknownMethod{method:message.to_call.clone()})?
+                };
+                method(self,&message)
             }
             fn can_accept(&self, message:Message)->bool{
-                static TABLE:LazyLock<HashMap<String,fn(&mut $self_name, args:Vec<String>)>> = LazyLock::new(||{$self_name::create_method_table()});
+                static TABLE:LazyLock<HashMap<String,fn(&mut $self_name, &Message)->Throws<Vec<u8>>>> = LazyLock::new(||{$self_name::create_method_table()});
                 (TABLE.get(message.to_call.as_ref()).is_some())
             }
+            fn describe(&self)->$crate::msg::ObjectSchema{
+                $crate::msg::ObjectSchema{
+                    name:stringify!($self_name).into(),
+                    methods:vec![$(
+                        $crate::msg::MethodSchema{
+                            name:stringify!($name).into(),
+                            args:vec![$(
+                                $crate::msg::ArgSchema{name:stringify!($y).into(), ty:stringify!($x).into()}
+                            ),*],
+                            returns:method_return_name!($($r)?).into(),
+                        }
+                    ),*],
+                }
+            }
         }
     };
 }
@@ -134,10 +418,127 @@ impl TestObject{
     pub fn test_2(&mut self, x1:i32){
         println!("x1:{}",x1*2);
     }
+    pub fn add(&mut self, x:i32, y:i32)->i32{
+        x+y
+    }
 }
 
 define_method!(TestObject,
     test_object,
     (fn test(x:i32, y:i32)),
-    (fn test_2(x1:i32))
-);
\ No newline at end of file
+    (fn test_2(x1:i32)),
+    (fn add(x:i32, y:i32)->i32)
+);
+#[tokio::test]
+async fn method_return_tests(){
+    use crate::events::{Event, EventHandler};
+    let mut obj = TestObject{};
+    assert_eq!(test_object_add_wrapper_direct(&mut obj, 2, 3).unwrap(), 5);
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    let sync = EventSync::new(sender);
+    let id = ObjectId::alloc();
+    sync.new_event_global(Event::AllocObject { id, object: Box::new(TestObject{}) }).unwrap();
+    tokio::spawn(async move {
+        let _ = handler.run(async |_| {}).await;
+    });
+    assert_eq!(test_object_add_wrapper(id, sync.clone(), 20, 22).await.unwrap(), 42);
+    test_object_test_2_wrapper(id, sync.clone(), 1).await.unwrap();
+    assert!(test_object_add_wrapper(ObjectId::alloc(), sync, 1, 1).await.is_err());
+}
+
+#[tokio::test]
+async fn call_error_tests(){
+    use crate::events::{Event, EventHandler};
+    let mut obj = TestObject{};
+    let mut call = |msg:Throws<Message>| {
+        obj.call(msg.unwrap()).unwrap_err().error_as::<CallError>().unwrap()
+    };
+    for encoding in [Encoding::MessagePack, Encoding::Json]{
+        let id = ObjectId::invalid();
+        assert_eq!(*call(Message::with_encoding(id, "tset", encoding, &[(); 0])), CallError::UnknownMethod { method: "tset".into() });
+        assert_eq!(*call(Message::with_encoding(id, "add", encoding, &(1,))), CallError::Arity { method: "add".into(), expected: 2, got: 1 });
+        assert_eq!(*call(Message::with_encoding(id, "add", encoding, &(1, 2, 3))), CallError::Arity { method: "add".into(), expected: 2, got: 3 });
+        assert!(matches!(*call(Message::with_encoding(id, "add", encoding, &(1, "two"))), CallError::Decode { index: 1, .. }));
+    }
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    let sync = EventSync::new(sender);
+    let id = ObjectId::alloc();
+    sync.new_event_global(Event::AllocObject { id, object: Box::new(TestObject{}) }).unwrap();
+    tokio::spawn(async move {
+        let _ = handler.run(async |_| {}).await;
+    });
+    sync.new_message(Message::new(id, "add", &(1,)).unwrap()).unwrap();
+    let err = sync.call_object::<i32>(Message::new(id, "nope", &[(); 0]).unwrap()).await.unwrap_err();
+    assert_eq!(*err.error_as::<CallError>().unwrap(), CallError::UnknownMethod { method: "nope".into() });
+    assert_eq!(test_object_add_wrapper(id, sync, 1, 2).await.unwrap(), 3);
+}
+
+#[test]
+fn encoding_tests(){
+    let mut obj = TestObject{};
+    let packed = Message::with_encoding(ObjectId::invalid(), "add", Encoding::MessagePack, &(400, 2)).unwrap();
+    let json = Message::with_encoding(ObjectId::invalid(), "add", Encoding::Json, &(400, 2)).unwrap();
+    assert!(packed.payload.len() < json.payload.len());
+    assert_eq!(std::str::from_utf8(&json.payload).unwrap().split_whitespace().collect::<String>(), "[400,2]");
+    let out = obj.call(packed).unwrap();
+    assert_eq!(Encoding::MessagePack.decode::<i32>(&out).unwrap(), 402);
+    let out = obj.call(json).unwrap();
+    assert_eq!(std::str::from_utf8(&out).unwrap(), "402");
+    assert_eq!(test_object_add_wrapper_direct(&mut obj, 1, 2).unwrap(), 3);
+    test_object_test_2_wrapper_direct(&mut obj, 1).unwrap();
+}
+
+#[tokio::test]
+async fn object_macro_tests(){
+    use crate::events::EventHandler;
+    struct Counter{
+        total:i64,
+    }
+    #[crate::object]
+    impl Counter{
+        pub fn add(&mut self, amount:i64, note:Option<String>)->i64{
+            _ = note;
+            self.total += amount;
+            self.total
+        }
+        pub fn total(&self)->i64{
+            self.total
+        }
+        pub fn reset(&mut self){
+            self.total = 0;
+        }
+        fn hidden(&self)->i64{
+            -1
+        }
+    }
+    let mut counter = Counter{total:0};
+    assert_eq!(counter.hidden(), -1);
+    let schema = counter.describe();
+    assert_eq!(schema.name.as_ref(), "Counter");
+    assert_eq!(schema.methods.iter().map(|x| x.name.as_ref()).collect::<Vec<_>>(), ["add", "total", "reset"]);
+    let add = schema.method("add").unwrap();
+    assert_eq!(add.args, vec![
+        ArgSchema{name:"amount".into(), ty:"i64".into()},
+        ArgSchema{name:"note".into(), ty:"Option<String>".into()},
+    ]);
+    assert_eq!(add.returns.as_ref(), "i64");
+    assert_eq!(schema.method("reset").unwrap().returns.as_ref(), "()");
+    assert!(!counter.can_accept(Message::new(ObjectId::invalid(), "hidden", &[(); 0]).unwrap()));
+    let err = counter.call(Message::new(ObjectId::invalid(), "add", &(3,)).unwrap()).unwrap_err();
+    assert!(matches!(*err.error_as::<CallError>().unwrap(), CallError::Arity { expected: 2, got: 1, .. }));
+
+    let (sender, mut handler) = EventHandler::<u32>::new();
+    let sync = EventSync::new(sender);
+    let id = sync.spawn_object(Box::new(counter)).unwrap();
+    tokio::spawn(async move {
+        let _ = handler.run(async |_| {}).await;
+    });
+    let client = CounterClient::new(id, sync);
+    assert_eq!(client.add(5, None).await.unwrap(), 5);
+    assert_eq!(client.add(2, Some("again".into())).await.unwrap(), 7);
+    client.reset().await.unwrap();
+    assert_eq!(client.total().await.unwrap(), 0);
+
+    let schema = TestObject{}.describe();
+    assert_eq!(schema.method("add").unwrap().args[1], ArgSchema{name:"y".into(), ty:"i32".into()});
+}
@@ -0,0 +1,353 @@
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::Mutex,
+};
+
+use crate::{
+    Exception, Throws,
+    events::ThreadSafeIsh,
+    server::{DEFAULT_MAX_HEADERS, DEFAULT_MAX_LINE, read_line},
+    throw,
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[async_trait]
+pub trait BodySource: ThreadSafeIsh {
+    /*
+     * returns None once the body is exhausted
+     * */
+    async fn next_chunk(&mut self) -> Throws<Option<Vec<u8>>>;
+}
+
+/*
+* a body that is read incrementally instead of being held in memory, clones share the same
+* underlying source so a chunk is only ever handed out once
+* */
+#[derive(Clone)]
+pub struct BodyStream {
+    source: Arc<Mutex<Box<dyn BodySource>>>,
+    len: Option<u64>,
+}
+
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl BodyStream {
+    pub fn new(source: impl BodySource, len: Option<u64>) -> Self {
+        Self {
+            source: Arc::new(Mutex::new(Box::new(source))),
+            len,
+        }
+    }
+
+    pub fn from_reader<R: AsyncRead + Unpin + ThreadSafeIsh>(reader: R, len: Option<u64>) -> Self {
+        Self::new(
+            ReaderBody {
+                reader,
+                remaining: len,
+            },
+            len,
+        )
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.len
+    }
+
+    pub async fn next_chunk(&self) -> Throws<Option<Vec<u8>>> {
+        self.source.lock().await.next_chunk().await
+    }
+
+    pub async fn collect(&self) -> Throws<Arc<[u8]>> {
+        let mut out = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            out.extend_from_slice(&chunk);
+        }
+        Ok(out.into())
+    }
+
+    /*
+     * like collect but gives up with None as soon as the body is known to be longer than max, what
+     * is left of it is not read
+     * */
+    pub async fn collect_limited(&self, max: u64) -> Throws<Option<Arc<[u8]>>> {
+        if self.len.is_some_and(|x| x > max) {
+            return Ok(None);
+        }
+        let mut out = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            if (out.len() + chunk.len()) as u64 > max {
+                return Ok(None);
+            }
+            out.extend_from_slice(&chunk);
+        }
+        Ok(Some(out.into()))
+    }
+
+    /*
+     * reads and throws away whatever is left so the connection is positioned at the next request
+     * */
+    pub async fn drain(&self) -> Throws<()> {
+        while self.next_chunk().await?.is_some() {}
+        Ok(())
+    }
+}
+
+struct ReaderBody<R> {
+    reader: R,
+    remaining: Option<u64>,
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + ThreadSafeIsh> BodySource for ReaderBody<R> {
+    async fn next_chunk(&mut self) -> Throws<Option<Vec<u8>>> {
+        let to_read = match self.remaining {
+            Some(0) => return Ok(None),
+            Some(x) => (x as usize).min(CHUNK_SIZE),
+            None => CHUNK_SIZE,
+        };
+        let mut buf = vec![0; to_read];
+        let count = self.reader.read(&mut buf).await?;
+        if count == 0 {
+            if self.remaining.is_some() {
+                throw!("body ended before its declared length");
+            }
+            return Ok(None);
+        }
+        buf.truncate(count);
+        if let Some(x) = &mut self.remaining {
+            *x -= count as u64;
+        }
+        Ok(Some(buf))
+    }
+}
+
+/*
+* a Content-Length body still sitting on a shared connection
+* */
+pub struct LengthBody<R> {
+    reader: Arc<Mutex<R>>,
+    remaining: u64,
+}
+
+impl<R> LengthBody<R> {
+    pub fn new(reader: Arc<Mutex<R>>, len: u64) -> Self {
+        Self {
+            reader,
+            remaining: len,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + ThreadSafeIsh> BodySource for LengthBody<R> {
+    async fn next_chunk(&mut self) -> Throws<Option<Vec<u8>>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let mut buf = vec![0; (self.remaining as usize).min(CHUNK_SIZE)];
+        let count = self.reader.lock().await.read(&mut buf).await?;
+        if count == 0 {
+            throw!("connection closed before the body was read");
+        }
+        buf.truncate(count);
+        self.remaining -= count as u64;
+        Ok(Some(buf))
+    }
+}
+
+/*
+* a Transfer-Encoding: chunked body still sitting on a shared connection, chunks are handed out
+* in pieces of at most CHUNK_SIZE however large the client says they are
+* */
+pub struct ChunkedBody<R> {
+    reader: Arc<Mutex<R>>,
+    max_chunk: u64,
+    remaining: u64,
+    started: bool,
+    done: bool,
+}
+
+impl<R> ChunkedBody<R> {
+    pub fn new(reader: Arc<Mutex<R>>, max_chunk: u64) -> Self {
+        Self {
+            reader,
+            max_chunk,
+            remaining: 0,
+            started: false,
+            done: false,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + ThreadSafeIsh> BodySource for ChunkedBody<R> {
+    async fn next_chunk(&mut self) -> Throws<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut stream = self.reader.lock().await;
+        if self.remaining == 0 {
+            if self.started {
+                read_chunk_end(&mut *stream).await?;
+            }
+            self.started = true;
+            match read_chunk_size(&mut *stream, self.max_chunk).await? {
+                Some(size) => self.remaining = size,
+                None => {
+                    self.done = true;
+                    return Ok(None);
+                }
+            }
+        }
+        let mut buf = vec![0; self.remaining.min(CHUNK_SIZE as u64) as usize];
+        let count = stream.read(&mut buf).await?;
+        if count == 0 {
+            throw!("connection closed before the chunk was read");
+        }
+        buf.truncate(count);
+        self.remaining -= count as u64;
+        Ok(Some(buf))
+    }
+}
+
+async fn read_body_line<S: AsyncRead + Unpin>(stream: &mut S) -> Throws<Vec<u8>> {
+    match read_line(stream, DEFAULT_MAX_LINE).await? {
+        Some(line) => Ok(line),
+        None => throw!("connection closed in the middle of a chunked body"),
+    }
+}
+
+/*
+* reads the size line of a chunk, the terminating zero sized chunk and its trailers produce None
+* */
+pub async fn read_chunk_size<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max: u64,
+) -> Throws<Option<u64>> {
+    let line = read_body_line(stream).await?;
+    let line = std::str::from_utf8(&line)?;
+    let size = line.split(';').next().unwrap_or("").trim();
+    let Ok(size) = u64::from_str_radix(size, 16) else {
+        throw!(format!("malformed chunk size:{:#?}", line));
+    };
+    if size > max {
+        throw!(format!(
+            "chunk of {} bytes is over the limit of {}",
+            size, max
+        ));
+    }
+    if size == 0 {
+        for _ in 0..=DEFAULT_MAX_HEADERS {
+            let trailer = read_body_line(stream).await?;
+            if trailer.is_empty() {
+                return Ok(None);
+            }
+        }
+        throw!(format!("more than {} trailer lines", DEFAULT_MAX_HEADERS));
+    }
+    Ok(Some(size))
+}
+
+async fn read_chunk_end<S: AsyncRead + Unpin>(stream: &mut S) -> Throws<()> {
+    let end = read_body_line(stream).await?;
+    if !end.is_empty() {
+        throw!("chunk was not terminated by CRLF");
+    }
+    Ok(())
+}
+
+/*
+* reads one chunk of a chunked body, chunks larger than max are refused and the rest is read in
+* pieces so memory only grows as the data actually arrives
+* */
+pub async fn read_chunk<S: AsyncRead + Unpin>(stream: &mut S, max: u64) -> Throws<Option<Vec<u8>>> {
+    let Some(size) = read_chunk_size(stream, max).await? else {
+        return Ok(None);
+    };
+    let mut buf = Vec::new();
+    let mut piece = vec![0; CHUNK_SIZE];
+    while (buf.len() as u64) < size {
+        let to_read = (size - buf.len() as u64).min(CHUNK_SIZE as u64) as usize;
+        stream.read_exact(&mut piece[..to_read]).await?;
+        buf.extend_from_slice(&piece[..to_read]);
+    }
+    read_chunk_end(stream).await?;
+    Ok(Some(buf))
+}
+
+/*
+* the whole of a chunked body, bodies longer than max are refused
+* */
+pub async fn read_chunked_body<S: AsyncRead + Unpin>(stream: &mut S, max: u64) -> Throws<Vec<u8>> {
+    let mut out = Vec::new();
+    while let Some(chunk) = read_chunk(stream, max - out.len() as u64).await? {
+        out.extend_from_slice(&chunk);
+    }
+    Ok(out)
+}
+
+pub async fn write_chunk<S: AsyncWrite + Unpin>(stream: &mut S, data: &[u8]) -> Throws<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    stream
+        .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+        .await?;
+    stream.write_all(data).await?;
+    stream.write_all(b"\r\n").await?;
+    Ok(())
+}
+
+pub async fn write_last_chunk<S: AsyncWrite + Unpin>(stream: &mut S) -> Throws<()> {
+    stream.write_all(b"0\r\n\r\n").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn chunked_tests() {
+    let mut out = Vec::new();
+    write_chunk(&mut out, b"hello ").await.unwrap();
+    write_chunk(&mut out, b"world").await.unwrap();
+    write_last_chunk(&mut out).await.unwrap();
+    assert_eq!(out, b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+    let mut stream = &out[..];
+    assert_eq!(
+        read_chunked_body(&mut stream, 64).await.unwrap(),
+        b"hello world"
+    );
+    let mut stream = &b"4;ext=1\r\nwiki\r\n0\r\nExpires: never\r\n\r\n"[..];
+    assert_eq!(read_chunked_body(&mut stream, 64).await.unwrap(), b"wiki");
+    let mut stream = &out[..];
+    assert!(read_chunked_body(&mut stream, 8).await.is_err());
+    let mut stream = &b"ffffffffffff\r\nabc"[..];
+    assert!(read_chunk(&mut stream, 1024).await.is_err());
+}
+
+#[tokio::test]
+async fn shared_body_tests() {
+    let reader = Arc::new(Mutex::new(&b"3\r\nabc\r\n0\r\n\r\nGET"[..]));
+    let body = BodyStream::new(ChunkedBody::new(reader.clone(), 1024), None);
+    assert_eq!(body.collect().await.unwrap().as_ref(), b"abc");
+    let mut rest = Vec::new();
+    reader.lock().await.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(rest, b"GET");
+    let reader = Arc::new(Mutex::new(&b"3\r\nabc\r\n4\r\ndefg\r\n0\r\n\r\n"[..]));
+    let body = BodyStream::new(ChunkedBody::new(reader.clone(), 1024), None);
+    assert!(body.collect_limited(5).await.unwrap().is_none());
+    let reader = Arc::new(Mutex::new(&b"3\r\nabc\r\n4\r\ndefg\r\n0\r\n\r\n"[..]));
+    let body = BodyStream::new(ChunkedBody::new(reader.clone(), 1024), None);
+    assert_eq!(
+        body.collect_limited(7).await.unwrap().unwrap().as_ref(),
+        b"abcdefg"
+    );
+}
@@ -0,0 +1,521 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+};
+
+use crate::{
+    Exception, Throw, Throws,
+    server::{
+        BodyFraming, DEFAULT_MAX_LINE, HTTPRequest, HTTPResponse, HeadLimits, HttpMethod,
+        HttpVersion, body::write_chunk, body::write_last_chunk, body_framing, http_parse_headers,
+        read_chunked_body, read_line,
+    },
+    throw,
+};
+
+/*
+* only plain http://host[:port]/path, the port defaults to 80 and an ipv6 host goes in brackets
+* */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    pub target: String,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> Throws<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            throw!(format!("unsupported url:{:#?}", url));
+        };
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            let Some((host, port)) = rest.split_once(']') else {
+                throw!(format!("unterminated ipv6 host:{:#?}", url));
+            };
+            match port.strip_prefix(':') {
+                Some(port) => (host, port.parse::<u16>()?),
+                None if port.is_empty() => (host, 80),
+                None => throw!(format!("malformed authority:{:#?}", url)),
+            }
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse::<u16>()?),
+                None => (authority, 80),
+            }
+        };
+        if host.is_empty() {
+            throw!(format!("url without a host:{:#?}", url));
+        }
+        let target = if target.starts_with('?') {
+            format!("/{}", target)
+        } else {
+            target.to_string()
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            target,
+        })
+    }
+
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == 80 {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+/*
+* Host and Content-Length are filled in unless the request already carries them, a request with
+* a streamed body of unknown length goes out chunked
+* */
+pub fn http_request_head(request: &HTTPRequest, host: &str) -> String {
+    let mut out = format!(
+        "{} {} {}\r\n",
+        request.method, request.target, request.version
+    );
+    if !request.headers.contains("Host") {
+        out += &format!("Host: {}\r\n", host);
+    }
+    for (k, v) in request.headers.iter() {
+        out += &format!("{}: {}\r\n", k, v);
+    }
+    let framed =
+        request.headers.contains("Content-Length") || request.headers.contains("Transfer-Encoding");
+    if !framed {
+        match &request.body {
+            Some(body) => match body.content_length() {
+                Some(len) => out += &format!("Content-Length: {}\r\n", len),
+                None => out += "Transfer-Encoding: chunked\r\n",
+            },
+            None if !request.msg.is_empty()
+                || matches!(request.method, HttpMethod::Post | HttpMethod::Put) =>
+            {
+                out += &format!("Content-Length: {}\r\n", request.msg.len());
+            }
+            None => {}
+        }
+    }
+    out += "\r\n";
+    out
+}
+
+pub async fn http_write_request<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &HTTPRequest,
+    host: &str,
+) -> Throws<()> {
+    let head = http_request_head(request, host);
+    stream.write_all(head.as_bytes()).await?;
+    match &request.body {
+        Some(body) => {
+            let chunked = head.contains("Transfer-Encoding: chunked\r\n");
+            while let Some(chunk) = body.next_chunk().await? {
+                if chunked {
+                    write_chunk(stream, &chunk).await?;
+                } else {
+                    stream.write_all(&chunk).await?;
+                }
+            }
+            if chunked {
+                write_last_chunk(stream).await?;
+            }
+        }
+        None => stream.write_all(&request.msg).await?,
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+/*
+* reads one response, the bool is whether the connection can carry another request afterwards.
+* a body without Content-Length or chunked framing runs until the server closes the connection,
+* bodies longer than max are refused
+* */
+pub async fn http_read_response<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    method: HttpMethod,
+    max: u64,
+) -> Throws<(HTTPResponse, bool)> {
+    let Some(line) = read_line(stream, DEFAULT_MAX_LINE).await? else {
+        throw!("connection closed before a response arrived");
+    };
+    let line = std::str::from_utf8(&line)?;
+    let mut parts = line.splitn(3, ' ');
+    let version = parts.next().throw()?.parse::<HttpVersion>()?;
+    let status = parts.next().throw()?.parse::<u16>()?;
+    let reason = parts.next().unwrap_or("").trim();
+    let headers = http_parse_headers(stream, HeadLimits::default()).await?;
+    let mut reusable = match version {
+        HttpVersion::Http10 => headers.has_token("Connection", "keep-alive"),
+        HttpVersion::Http11 => !headers.has_token("Connection", "close"),
+    };
+    let bodyless = method == HttpMethod::Head
+        || (100..200).contains(&status)
+        || status == 204
+        || status == 304;
+    let data: Vec<u8> = if bodyless {
+        Vec::new()
+    } else {
+        match body_framing(&headers)? {
+            BodyFraming::Chunked => read_chunked_body(stream, max).await?,
+            BodyFraming::Length(len) if len > max => {
+                throw!(format!(
+                    "response body of {} bytes is over the limit of {}",
+                    len, max
+                ));
+            }
+            BodyFraming::Length(len) => {
+                let mut buf = vec![0; len as usize];
+                stream.read_exact(&mut buf).await?;
+                buf
+            }
+            BodyFraming::Empty => {
+                reusable = false;
+                let mut buf = Vec::new();
+                stream.take(max + 1).read_to_end(&mut buf).await?;
+                if buf.len() as u64 > max {
+                    throw!(format!("response body is over the limit of {}", max));
+                }
+                buf
+            }
+        }
+    };
+    let mut response = HTTPResponse::status(status).with_body(data);
+    response.reason = reason.into();
+    response.headers = headers;
+    Ok((response, reusable))
+}
+
+struct ClientConnection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    idle_since: Instant,
+}
+
+/*
+* keep-alive connections are parked per host:port after a response and handed to the next
+* request to the same place. when a parked connection fails an idempotent request is retried once
+* on a fresh one, anything else could already have been acted on by the server so the error is
+* returned instead
+* */
+#[derive(Clone)]
+pub struct HttpClient {
+    pool: Arc<std::sync::Mutex<HashMap<String, Vec<ClientConnection>>>>,
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+    timeout: Duration,
+    max_response_body: u64,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self {
+            pool: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            max_idle_per_host: 8,
+            idle_timeout: Duration::from_secs(30),
+            timeout: Duration::from_secs(30),
+            max_response_body: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_idle_per_host(mut self, count: usize) -> Self {
+        self.max_idle_per_host = count;
+        self
+    }
+
+    /*
+     * parked connections older than this are dropped instead of reused
+     * */
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /*
+     * covers connecting, sending the request and reading the whole response
+     * */
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /*
+     * responses with a longer body fail instead of being read into memory
+     * */
+    pub fn max_response_body(mut self, bytes: u64) -> Self {
+        self.max_response_body = bytes;
+        self
+    }
+
+    pub fn idle_connections(&self, authority: &str) -> usize {
+        self.pool
+            .lock()
+            .map(|x| x.get(authority).map(|x| x.len()).unwrap_or(0))
+            .unwrap_or(0)
+    }
+
+    pub async fn get(&self, url: &str) -> Throws<HTTPResponse> {
+        self.send(url, HTTPRequest::new(HttpMethod::Get, "/")).await
+    }
+
+    pub async fn post(
+        &self,
+        url: &str,
+        content_type: &str,
+        body: impl Into<Arc<[u8]>>,
+    ) -> Throws<HTTPResponse> {
+        let req = HTTPRequest::new(HttpMethod::Post, "/")
+            .with_header("Content-Type", content_type)
+            .with_body(body);
+        self.send(url, req).await
+    }
+
+    pub async fn put(
+        &self,
+        url: &str,
+        content_type: &str,
+        body: impl Into<Arc<[u8]>>,
+    ) -> Throws<HTTPResponse> {
+        let req = HTTPRequest::new(HttpMethod::Put, "/")
+            .with_header("Content-Type", content_type)
+            .with_body(body);
+        self.send(url, req).await
+    }
+
+    pub async fn delete(&self, url: &str) -> Throws<HTTPResponse> {
+        self.send(url, HTTPRequest::new(HttpMethod::Delete, "/"))
+            .await
+    }
+
+    /*
+     * sends request to url, the request's method, headers and body are kept but its target is
+     * replaced by the one in url
+     * */
+    pub async fn send(&self, url: &str, mut request: HTTPRequest) -> Throws<HTTPResponse> {
+        let url = HttpUrl::parse(url)?;
+        let fresh = HTTPRequest::new(request.method, &url.target);
+        request.target = fresh.target;
+        request.path = fresh.path;
+        request.query = fresh.query;
+        match tokio::time::timeout(self.timeout, self.send_to(&url, &request)).await {
+            Ok(res) => res,
+            Err(_) => throw!(format!("request to {} timed out", url.authority())),
+        }
+    }
+
+    async fn send_to(&self, url: &HttpUrl, request: &HTTPRequest) -> Throws<HTTPResponse> {
+        let authority = url.authority();
+        if let Some(con) = self.checkout(&authority) {
+            match self.exchange(con, &authority, request).await {
+                Ok(response) => return Ok(response),
+                Err(_) if request.body.is_none() && request.method.is_idempotent() => {}
+                Err(x) => return Err(x),
+            }
+        }
+        let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        let con = ClientConnection {
+            reader: BufReader::new(reader),
+            writer,
+            idle_since: Instant::now(),
+        };
+        self.exchange(con, &authority, request).await
+    }
+
+    async fn exchange(
+        &self,
+        mut con: ClientConnection,
+        authority: &str,
+        request: &HTTPRequest,
+    ) -> Throws<HTTPResponse> {
+        http_write_request(&mut con.writer, request, authority).await?;
+        let (response, reusable) =
+            http_read_response(&mut con.reader, request.method, self.max_response_body).await?;
+        if reusable && !request.headers.has_token("Connection", "close") {
+            self.checkin(authority, con);
+        }
+        Ok(response)
+    }
+
+    fn checkout(&self, authority: &str) -> Option<ClientConnection> {
+        let mut pool = self.pool.lock().ok()?;
+        let list = pool.get_mut(authority)?;
+        while let Some(con) = list.pop() {
+            if con.idle_since.elapsed() < self.idle_timeout {
+                return Some(con);
+            }
+        }
+        None
+    }
+
+    fn checkin(&self, authority: &str, mut con: ClientConnection) {
+        let Ok(mut pool) = self.pool.lock() else {
+            return;
+        };
+        let list = pool.entry(authority.to_string()).or_default();
+        if list.len() < self.max_idle_per_host {
+            con.idle_since = Instant::now();
+            list.push(con);
+        }
+    }
+}
+
+#[test]
+fn url_tests() {
+    let url = HttpUrl::parse("http://localhost:8080/a/b?c=d").unwrap();
+    assert_eq!(url.host, "localhost");
+    assert_eq!(url.port, 8080);
+    assert_eq!(url.target, "/a/b?c=d");
+    let url = HttpUrl::parse("http://example.com").unwrap();
+    assert_eq!((url.port, url.target.as_str()), (80, "/"));
+    assert_eq!(url.authority(), "example.com");
+    assert!(HttpUrl::parse("https://example.com").is_err());
+    let url = HttpUrl::parse("http://[::1]:8080/x").unwrap();
+    assert_eq!((url.host.as_str(), url.port), ("::1", 8080));
+    assert_eq!(url.authority(), "[::1]:8080");
+    assert_eq!(HttpUrl::parse("http://[::1]").unwrap().port, 80);
+    assert!(HttpUrl::parse("http://[::1/").is_err());
+}
+
+#[tokio::test]
+async fn read_response_tests() {
+    let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\nHTTP/1.0 404 Not Found\r\n\r\ngone";
+    let mut stream = &raw[..];
+    let (response, reusable) = http_read_response(&mut stream, HttpMethod::Get, 1024)
+        .await
+        .unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.data.as_ref(), b"abc");
+    assert!(reusable);
+    let (response, reusable) = http_read_response(&mut stream, HttpMethod::Get, 1024)
+        .await
+        .unwrap();
+    assert_eq!(
+        (response.status, response.reason.as_ref()),
+        (404, "Not Found")
+    );
+    assert_eq!(response.data.as_ref(), b"gone");
+    assert!(!reusable);
+    let mut stream = &b"HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n"[..];
+    assert!(
+        http_read_response(&mut stream, HttpMethod::Get, 1024)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn loopback_tests() {
+    use crate::events::{Daemon, EventHandler, EventSync};
+    use crate::server::{
+        BodyStream, HttpConfig, HttpResponseType, HttpServer, RouteRequest, ServerEvent,
+    };
+    let (sender, _handler) = EventHandler::<ServerEvent>::new();
+    let config = HttpConfig::new()
+        .get("/hello", async |_| {
+            Ok(HTTPResponse::new(HttpResponseType::Text, b"hi".to_vec()))
+        })
+        .route_any("/echo", async |req: RouteRequest| {
+            let mut body = req.request.method.to_string().into_bytes();
+            body.extend_from_slice(&req.request.read_body().await?);
+            Ok(HTTPResponse::new(HttpResponseType::Text, body))
+        })
+        .get("/stream", async |_| {
+            Ok(HTTPResponse::status(200)
+                .with_stream(BodyStream::from_reader(&b"streamed body"[..], None)))
+        })
+        .build();
+    let mut server = HttpServer::try_new("127.0.0.1:0", config, EventSync::new(sender))
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run().await });
+    let client = HttpClient::new();
+    let base = format!("http://{}", addr);
+    let res = client.get(&format!("{}/hello", base)).await.unwrap();
+    assert_eq!((res.status, res.data.as_ref()), (200, &b"hi"[..]));
+    let res = client
+        .post(&format!("{}/echo", base), "text/plain", b"abc".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(res.data.as_ref(), b"POSTabc");
+    let res = client
+        .put(&format!("{}/echo", base), "text/plain", b"x".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(res.data.as_ref(), b"PUTx");
+    let res = client.delete(&format!("{}/echo", base)).await.unwrap();
+    assert_eq!(res.data.as_ref(), b"DELETE");
+    let res = client.get(&format!("{}/stream", base)).await.unwrap();
+    assert_eq!(res.header("Transfer-Encoding"), Some("chunked"));
+    assert_eq!(res.data.as_ref(), b"streamed body");
+    assert_eq!(client.idle_connections(&addr.to_string()), 1);
+}
+
+#[tokio::test]
+async fn retry_tests() {
+    use crate::server::http_get_request;
+    use tokio::net::TcpListener;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = seen.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut con, _) = listener.accept().await.unwrap();
+            let log = log.clone();
+            tokio::spawn(async move {
+                let req = http_get_request(&mut con, 1024).await.unwrap();
+                log.lock().unwrap().push(req.method);
+                con.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+                let req = http_get_request(&mut con, 1024).await.unwrap();
+                log.lock().unwrap().push(req.method);
+            });
+        }
+    });
+    let client = HttpClient::new();
+    let url = format!("http://{}/", addr);
+    client.get(&url).await.unwrap();
+    assert!(
+        client
+            .post(&url, "text/plain", b"x".to_vec())
+            .await
+            .is_err()
+    );
+    client.get(&url).await.unwrap();
+    assert_eq!(client.get(&url).await.unwrap().status, 200);
+    let seen = seen.lock().unwrap().clone();
+    let posts = seen.iter().filter(|x| **x == HttpMethod::Post).count();
+    assert_eq!(posts, 1);
+    assert_eq!(seen.len(), 5);
+}
@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use flate2::{
+    Compression,
+    write::{GzEncoder, ZlibEncoder},
+};
+
+use crate::{Throws, server::HTTPResponse};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    /*
+     * deflate on the wire is the zlib format, not a raw deflate stream
+     * */
+    pub fn encode(&self, data: &[u8]) -> Throws<Vec<u8>> {
+        Ok(match self {
+            ContentEncoding::Gzip => {
+                let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+                enc.write_all(data)?;
+                enc.finish()?
+            }
+            ContentEncoding::Deflate => {
+                let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+                enc.write_all(data)?;
+                enc.finish()?
+            }
+        })
+    }
+}
+
+/*
+* picks the best of gzip and deflate from an Accept-Encoding header by q value, gzip wins ties and
+* is what * stands for
+* */
+pub fn negotiate_encoding(accept: &str) -> Option<ContentEncoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let mut q = 1.0f32;
+        for p in parts {
+            if let Some((k, v)) = p.split_once('=')
+                && k.trim().eq_ignore_ascii_case("q")
+            {
+                q = v.trim().parse().unwrap_or(0.0);
+            }
+        }
+        match name.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "deflate" => deflate = Some(q),
+            "*" => any = Some(q),
+            _ => {}
+        }
+    }
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    if gzip <= 0.0 && deflate <= 0.0 {
+        None
+    } else if gzip >= deflate {
+        Some(ContentEncoding::Gzip)
+    } else {
+        Some(ContentEncoding::Deflate)
+    }
+}
+
+/*
+* images, video and archives are already compressed and only get bigger
+* */
+pub fn is_compressible(content_type: &str) -> bool {
+    let ct = content_type.to_ascii_lowercase();
+    ct.starts_with("text/")
+        || ["json", "javascript", "xml", "wasm"]
+            .iter()
+            .any(|x| ct.contains(x))
+}
+
+/*
+* encodes a buffered body in place, anything streamed, already encoded, partial, bodyless, under
+* the threshold or not worth compressing goes out untouched
+* */
+pub fn compress_response(
+    mut response: HTTPResponse,
+    encoding: Option<ContentEncoding>,
+    threshold: u64,
+) -> Throws<HTTPResponse> {
+    let Some(encoding) = encoding else {
+        return Ok(response);
+    };
+    if response.status < 200
+        || matches!(response.status, 204 | 206 | 304)
+        || response.stream.is_some()
+        || response.headers.contains("Content-Encoding")
+        || (response.data.len() as u64) < threshold
+        || !response.header("Content-Type").is_some_and(is_compressible)
+    {
+        return Ok(response);
+    }
+    let data = encoding.encode(&response.data)?;
+    response
+        .headers
+        .insert("Content-Encoding", encoding.as_str());
+    response.headers.append("Vary", "Accept-Encoding");
+    response.headers.remove("Content-Length");
+    Ok(response.with_body(data))
+}
+
+type EncodedEntries = HashMap<(PathBuf, ContentEncoding), (Arc<str>, Arc<[u8]>)>;
+
+/*
+* encoded copies of served files keyed by path, an entry only counts while the file still has the
+* etag it was encoded from. once max_bytes is reached the whole cache is dropped and refilled
+* */
+pub struct EncodedCache {
+    entries: std::sync::Mutex<EncodedEntries>,
+    max_bytes: usize,
+}
+
+impl Debug for EncodedCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries = self.entries.lock().map(|x| x.len()).unwrap_or(0);
+        f.debug_struct("EncodedCache")
+            .field("entries", &entries)
+            .field("max_bytes", &self.max_bytes)
+            .finish()
+    }
+}
+
+impl EncodedCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: std::sync::Mutex::new(HashMap::new()),
+            max_bytes,
+        }
+    }
+
+    pub fn get(&self, path: &Path, encoding: ContentEncoding, etag: &str) -> Option<Arc<[u8]>> {
+        let entries = self.entries.lock().ok()?;
+        let (tag, data) = entries.get(&(path.to_path_buf(), encoding))?;
+        if tag.as_ref() != etag {
+            return None;
+        }
+        Some(data.clone())
+    }
+
+    pub fn insert(&self, path: &Path, encoding: ContentEncoding, etag: &str, data: Arc<[u8]>) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let used: usize = entries.values().map(|x| x.1.len()).sum();
+        if used + data.len() > self.max_bytes {
+            entries.clear();
+        }
+        if data.len() <= self.max_bytes {
+            entries.insert((path.to_path_buf(), encoding), (etag.into(), data));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|x| x.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn negotiate_tests() {
+    assert_eq!(
+        negotiate_encoding("gzip, deflate, br"),
+        Some(ContentEncoding::Gzip)
+    );
+    assert_eq!(
+        negotiate_encoding("gzip;q=0.5, deflate"),
+        Some(ContentEncoding::Deflate)
+    );
+    assert_eq!(negotiate_encoding("*;q=0.1"), Some(ContentEncoding::Gzip));
+    assert_eq!(negotiate_encoding("gzip;q=0, br"), None);
+    assert_eq!(negotiate_encoding("identity"), None);
+}
+
+#[test]
+fn compress_response_tests() {
+    use crate::server::HttpResponseType;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    let body = "{\"a\":1}".repeat(100);
+    let response = HTTPResponse::new(HttpResponseType::Json, body.as_bytes().to_vec());
+    let out = compress_response(response.clone(), Some(ContentEncoding::Gzip), 64).unwrap();
+    assert_eq!(out.header("Content-Encoding"), Some("gzip"));
+    assert!(out.data.len() < body.len());
+    let mut decoded = String::new();
+    GzDecoder::new(&out.data[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, body);
+    let small = compress_response(response.clone(), Some(ContentEncoding::Gzip), 4096).unwrap();
+    assert!(small.header("Content-Encoding").is_none());
+    let png = HTTPResponse::new(HttpResponseType::Png, body.as_bytes().to_vec());
+    let png = compress_response(png, Some(ContentEncoding::Gzip), 64).unwrap();
+    assert!(png.header("Content-Encoding").is_none());
+}
@@ -0,0 +1,525 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    Throws,
+    server::{
+        BodyStream, HTTPRequest, HTTPResponse, HttpConfig, HttpMethod, HttpResponseType,
+        compress::is_compressible,
+        reason_phrase,
+        router::{percent_decode, percent_encode},
+    },
+};
+
+/*
+* where a request path ends up inside serve_dir
+* */
+#[derive(Debug, Clone, PartialEq)]
+pub enum StaticTarget {
+    File(PathBuf),
+    Listing(PathBuf),
+    Redirect(String),
+    NotFound,
+    Forbidden,
+}
+
+/*
+* directories resolve to their index.html, a directory requested without its trailing slash is
+* redirected so relative links inside the page keep working, anything that canonicalizes to
+* outside of serve_dir is forbidden
+* */
+pub fn resolve_static(serve_dir: &str, path: &str, listings: bool) -> Throws<StaticTarget> {
+    let decoded = percent_decode(path);
+    if decoded.contains('\0') {
+        return Ok(StaticTarget::NotFound);
+    }
+    let root = Path::new(serve_dir).canonicalize()?;
+    let Ok(full) = Path::new(&format!("{}/{}", serve_dir, decoded)).canonicalize() else {
+        return Ok(StaticTarget::NotFound);
+    };
+    if !full.starts_with(&root) {
+        return Ok(StaticTarget::Forbidden);
+    }
+    if !full.is_dir() {
+        return Ok(StaticTarget::File(full));
+    }
+    if !path.ends_with('/') {
+        return Ok(StaticTarget::Redirect(format!("{}/", path)));
+    }
+    let index = full.join("index.html");
+    if index.is_file() {
+        return Ok(StaticTarget::File(index));
+    }
+    if listings {
+        return Ok(StaticTarget::Listing(full));
+    }
+    Ok(StaticTarget::Forbidden)
+}
+
+pub fn error_page(status: u16) -> HTTPResponse {
+    let title = format!("{} {}", status, reason_phrase(status));
+    let body = format!(
+        "<!DOCTYPE html>\n<html><head><title>{}</title></head><body><h1>{}</h1></body></html>\n",
+        title, title
+    );
+    HTTPResponse::status(status)
+        .with_header("Content-Type", HttpResponseType::Html.content_type())
+        .with_body(body.into_bytes())
+}
+
+pub fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            '"' => out += "&quot;",
+            '\'' => out += "&#39;",
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn directory_listing(dir: &Path, url_path: &str) -> Throws<HTTPResponse> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        entries.push((name, entry.file_type()?.is_dir()));
+    }
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let title = html_escape(&percent_decode(url_path));
+    let mut body = format!(
+        "<!DOCTYPE html>\n<html><head><title>Index of {}</title></head><body><h1>Index of {}</h1><ul>\n",
+        title, title
+    );
+    if url_path != "/" {
+        body += "<li><a href=\"../\">../</a></li>\n";
+    }
+    for (name, is_dir) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        body += &format!(
+            "<li><a href=\"{}{}\">{}{}</a></li>\n",
+            percent_encode(&name),
+            slash,
+            html_escape(&name),
+            slash
+        );
+    }
+    body += "</ul></body></html>\n";
+    Ok(HTTPResponse::new(HttpResponseType::Html, body.into_bytes()))
+}
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/*
+* IMF-fixdate, the only format servers are allowed to send
+* */
+pub fn http_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0) as i64;
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/*
+* only understands IMF-fixdate, anything else is treated as if the header was not sent
+* */
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = s.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|x| *x == month)? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|x| x.parse::<i64>().ok());
+    let (h, m, sec) = (hms.next()??, hms.next()??, hms.next()??);
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + h * 3600 + m * 60 + sec;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/*
+* a single bytes= range, inclusive on both ends. multiple ranges and anything malformed fall back
+* to sending the whole file which the spec allows
+* */
+pub fn parse_range(header: &str, len: u64) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 || len == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            ByteRange::Partial(len.saturating_sub(suffix), len - 1)
+        }
+        (Ok(start), Err(_)) if end.is_empty() => {
+            if start >= len {
+                return ByteRange::Unsatisfiable;
+            }
+            ByteRange::Partial(start, len - 1)
+        }
+        (Ok(start), Ok(end)) if start <= end => {
+            if start >= len {
+                return ByteRange::Unsatisfiable;
+            }
+            ByteRange::Partial(start, end.min(len - 1))
+        }
+        _ => ByteRange::Full,
+    }
+}
+
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(|x| x.trim())
+        .any(|x| x == "*" || x.trim_start_matches("W/") == etag)
+}
+
+fn modified_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+/*
+* If-None-Match wins over If-Modified-Since when both are sent
+* */
+fn not_modified(req: &HTTPRequest, etag: &str, modified: SystemTime) -> bool {
+    if let Some(tags) = req.header("If-None-Match") {
+        return etag_matches(tags, etag);
+    }
+    req.header("If-Modified-Since")
+        .and_then(parse_http_date)
+        .is_some_and(|since| modified_secs(modified) <= modified_secs(since))
+}
+
+/*
+* If-Range only lets the range through when the client still has the current version
+* */
+fn range_applies(req: &HTTPRequest, etag: &str, modified: SystemTime) -> bool {
+    match req.header("If-Range") {
+        None => true,
+        Some(x) if x.trim().starts_with('"') => x.trim() == etag,
+        Some(x) => parse_http_date(x).is_some_and(|x| modified_secs(x) == modified_secs(modified)),
+    }
+}
+
+/*
+* the GET/HEAD path for serve_dir, files larger than max_buffered_body are streamed. HEAD goes
+* through the same validators, ranges and encoding as GET but only opens the file when the
+* encoded length is not cached yet. whole-file GETs of compressible types are encoded once and
+* then served from config.encoded_cache
+* */
+pub async fn serve_static(config: &HttpConfig, req: &HTTPRequest) -> Throws<HTTPResponse> {
+    let target = resolve_static(&config.serve_dir, &req.path, config.directory_listing)?;
+    let path = match target {
+        StaticTarget::File(path) => path,
+        StaticTarget::Listing(dir) => return directory_listing(&dir, &req.path),
+        StaticTarget::Redirect(location) => {
+            return Ok(HTTPResponse::status(301).with_header("Location", location));
+        }
+        StaticTarget::NotFound => return Ok(error_page(404)),
+        StaticTarget::Forbidden => return Ok(error_page(403)),
+    };
+    let meta = tokio::fs::metadata(&path).await?;
+    let len = meta.len();
+    let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+    let mime = config.mime_types.for_path(&path);
+    let compressible = config.compression && is_compressible(mime);
+    let encoding = config.negotiate_encoding(req).filter(|_| {
+        compressible
+            && matches!(req.method, HttpMethod::Get | HttpMethod::Head)
+            && req.header("Range").is_none()
+            && len >= config.compression_threshold
+            && len <= config.max_buffered_body
+    });
+    let etag = format!(
+        "\"{:x}-{:x}{}\"",
+        len,
+        modified
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos())
+            .unwrap_or(0),
+        encoding
+            .map(|x| format!("-{}", x.as_str()))
+            .unwrap_or_default()
+    );
+    let validators = |mut response: HTTPResponse| {
+        if compressible {
+            response.headers.append("Vary", "Accept-Encoding");
+        }
+        response
+            .with_header("ETag", etag.as_str())
+            .with_header("Last-Modified", http_date(modified))
+            .with_header("Accept-Ranges", "bytes")
+    };
+    if not_modified(req, &etag, modified) {
+        return Ok(validators(HTTPResponse::status(304)));
+    }
+    let range = match req.header("Range") {
+        Some(x) if range_applies(req, &etag, modified) => parse_range(x, len),
+        _ => ByteRange::Full,
+    };
+    let (response, start, count) = match range {
+        ByteRange::Full => (HTTPResponse::status(200), 0, len),
+        ByteRange::Partial(start, end) => (
+            HTTPResponse::status(206)
+                .with_header("Content-Range", format!("bytes {}-{}/{}", start, end, len)),
+            start,
+            end - start + 1,
+        ),
+        ByteRange::Unsatisfiable => {
+            return Ok(validators(error_page(416))
+                .with_header("Content-Range", format!("bytes */{}", len)));
+        }
+    };
+    let response = validators(response).with_header("Content-Type", mime);
+    if let Some(encoding) = encoding {
+        let data = match config.encoded_cache.get(&path, encoding, &etag) {
+            Some(data) => data,
+            None => {
+                let data: Arc<[u8]> = encoding.encode(&tokio::fs::read(&path).await?)?.into();
+                config
+                    .encoded_cache
+                    .insert(&path, encoding, &etag, data.clone());
+                data
+            }
+        };
+        let response = response.with_header("Content-Encoding", encoding.as_str());
+        if req.method == HttpMethod::Head {
+            return Ok(response.with_header("Content-Length", data.len().to_string()));
+        }
+        return Ok(response.with_body(data));
+    }
+    if req.method == HttpMethod::Head {
+        return Ok(response.with_header("Content-Length", count.to_string()));
+    }
+    let mut file = tokio::fs::File::open(&path).await?;
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await?;
+    }
+    if count > config.max_buffered_body {
+        return Ok(response.with_stream(BodyStream::from_reader(file.take(count), Some(count))));
+    }
+    let mut data = vec![0; count as usize];
+    file.read_exact(&mut data).await?;
+    Ok(response.with_body(data))
+}
+
+#[test]
+fn resolve_static_tests() {
+    let root = std::env::temp_dir().join(format!("rtils_static_{}", std::process::id()));
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::create_dir_all(root.join("empty")).unwrap();
+    std::fs::write(root.join("docs/index.html"), "<p>docs</p>").unwrap();
+    std::fs::write(root.join("a b.css"), "body{}").unwrap();
+    let dir = root.to_str().unwrap();
+    let canon = root.canonicalize().unwrap();
+    assert_eq!(
+        resolve_static(dir, "/a%20b.css", false).unwrap(),
+        StaticTarget::File(canon.join("a b.css"))
+    );
+    assert_eq!(
+        resolve_static(dir, "/docs", false).unwrap(),
+        StaticTarget::Redirect("/docs/".into())
+    );
+    assert_eq!(
+        resolve_static(dir, "/docs/", false).unwrap(),
+        StaticTarget::File(canon.join("docs/index.html"))
+    );
+    assert_eq!(
+        resolve_static(dir, "/empty/", false).unwrap(),
+        StaticTarget::Forbidden
+    );
+    assert_eq!(
+        resolve_static(dir, "/empty/", true).unwrap(),
+        StaticTarget::Listing(canon.join("empty"))
+    );
+    assert_eq!(
+        resolve_static(dir, "/missing.png", false).unwrap(),
+        StaticTarget::NotFound
+    );
+    assert_eq!(
+        resolve_static(dir, "/../", false).unwrap(),
+        StaticTarget::Forbidden
+    );
+    let listing = directory_listing(&canon, "/").unwrap();
+    let body = std::str::from_utf8(&listing.data).unwrap();
+    assert!(body.contains("<a href=\"docs/\">docs/</a>"));
+    assert!(body.contains("<a href=\"a%20b.css\">a b.css</a>"));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn http_date_tests() {
+    let t = UNIX_EPOCH + Duration::from_secs(784111777);
+    assert_eq!(http_date(t), "Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(t));
+    assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+    assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+}
+
+#[test]
+fn range_tests() {
+    assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+    assert_eq!(
+        parse_range("bytes=900-", 1000),
+        ByteRange::Partial(900, 999)
+    );
+    assert_eq!(
+        parse_range("bytes=-100", 1000),
+        ByteRange::Partial(900, 999)
+    );
+    assert_eq!(
+        parse_range("bytes=500-5000", 1000),
+        ByteRange::Partial(500, 999)
+    );
+    assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+    assert_eq!(parse_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+    assert_eq!(parse_range("items=0-1", 1000), ByteRange::Full);
+}
+
+#[tokio::test]
+async fn conditional_range_tests() {
+    let root = std::env::temp_dir().join(format!("rtils_cond_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("data.txt"), "0123456789").unwrap();
+    let config = HttpConfig::new()
+        .serve_dir(root.to_str().unwrap())
+        .max_buffered_body(4)
+        .build();
+    let full = serve_static(&config, &HTTPRequest::new(HttpMethod::Get, "/data.txt"))
+        .await
+        .unwrap();
+    assert_eq!(full.status, 200);
+    let etag = full.header("ETag").unwrap().to_string();
+    let modified = full.header("Last-Modified").unwrap().to_string();
+    assert_eq!(
+        full.stream.unwrap().collect().await.unwrap().as_ref(),
+        b"0123456789"
+    );
+
+    let mut req = HTTPRequest::new(HttpMethod::Get, "/data.txt");
+    req.headers.insert("If-None-Match", etag.as_str());
+    assert_eq!(serve_static(&config, &req).await.unwrap().status, 304);
+    let mut req = HTTPRequest::new(HttpMethod::Get, "/data.txt");
+    req.headers.insert("If-Modified-Since", modified.as_str());
+    assert_eq!(serve_static(&config, &req).await.unwrap().status, 304);
+
+    let mut req = HTTPRequest::new(HttpMethod::Get, "/data.txt");
+    req.headers.insert("Range", "bytes=2-4");
+    let partial = serve_static(&config, &req).await.unwrap();
+    assert_eq!(partial.status, 206);
+    assert_eq!(partial.header("Content-Range"), Some("bytes 2-4/10"));
+    assert_eq!(partial.data.as_ref(), b"234");
+    req.headers.insert("If-Range", "\"stale\"");
+    assert_eq!(serve_static(&config, &req).await.unwrap().status, 200);
+    req.headers.insert("Range", "bytes=20-");
+    req.headers.remove("If-Range");
+    let bad = serve_static(&config, &req).await.unwrap();
+    assert_eq!(bad.status, 416);
+    assert_eq!(bad.header("Content-Range"), Some("bytes */10"));
+
+    let head = serve_static(&config, &HTTPRequest::new(HttpMethod::Head, "/data.txt"))
+        .await
+        .unwrap();
+    assert_eq!(head.header("Content-Length"), Some("10"));
+    assert!(head.data.is_empty() && head.stream.is_none());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn static_compression_tests() {
+    let root = std::env::temp_dir().join(format!("rtils_gzip_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("big.json"), "[1,2,3]".repeat(1000)).unwrap();
+    let config = HttpConfig::new()
+        .serve_dir(root.to_str().unwrap())
+        .compression_threshold(100)
+        .build();
+    let mut req = HTTPRequest::new(HttpMethod::Get, "/big.json");
+    req.headers.insert("Accept-Encoding", "deflate");
+    let first = serve_static(&config, &req).await.unwrap();
+    assert_eq!(first.header("Content-Encoding"), Some("deflate"));
+    assert_eq!(first.header("Vary"), Some("Accept-Encoding"));
+    assert!(first.data.len() < 7000);
+    assert_eq!(config.encoded_cache.len(), 1);
+    let second = serve_static(&config, &req).await.unwrap();
+    assert!(Arc::ptr_eq(&first.data, &second.data));
+    req.headers
+        .insert("If-None-Match", first.header("ETag").unwrap().to_string());
+    assert_eq!(serve_static(&config, &req).await.unwrap().status, 304);
+    let plain = serve_static(&config, &HTTPRequest::new(HttpMethod::Get, "/big.json"))
+        .await
+        .unwrap();
+    assert!(plain.header("Content-Encoding").is_none());
+    assert_ne!(plain.header("ETag"), first.header("ETag"));
+    let mut req = HTTPRequest::new(HttpMethod::Head, "/big.json");
+    req.headers.insert("Accept-Encoding", "deflate");
+    let head = serve_static(&config, &req).await.unwrap();
+    assert_eq!(head.header("Content-Encoding"), Some("deflate"));
+    assert_eq!(head.header("ETag"), first.header("ETag"));
+    assert_eq!(
+        head.header("Content-Length"),
+        Some(first.data.len().to_string().as_str())
+    );
+    assert!(head.data.is_empty());
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,112 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+const DEFAULT_MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=UTF-8"),
+    ("htm", "text/html; charset=UTF-8"),
+    ("css", "text/css; charset=UTF-8"),
+    ("js", "text/javascript; charset=UTF-8"),
+    ("mjs", "text/javascript; charset=UTF-8"),
+    ("json", "application/json; charset=UTF-8"),
+    ("map", "application/json; charset=UTF-8"),
+    ("webmanifest", "application/manifest+json"),
+    ("txt", "text/plain; charset=UTF-8"),
+    ("md", "text/markdown; charset=UTF-8"),
+    ("csv", "text/csv; charset=UTF-8"),
+    ("xml", "application/xml"),
+    ("glsl", "text/plain; charset=UTF-8"),
+    ("obj", "text/plain; charset=UTF-8"),
+    ("mtl", "text/plain; charset=UTF-8"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+];
+
+/*
+* file extension to Content-Type, extensions are matched case insensitively and anything unknown
+* is sent as application/octet-stream
+* */
+#[derive(Debug, Clone)]
+pub struct MimeRegistry {
+    types: HashMap<String, Arc<str>>,
+    fallback: Arc<str>,
+}
+
+impl Default for MimeRegistry {
+    fn default() -> Self {
+        let mut out = Self {
+            types: HashMap::new(),
+            fallback: "application/octet-stream".into(),
+        };
+        for (ext, mime) in DEFAULT_MIME_TYPES {
+            out.insert(ext, mime);
+        }
+        out
+    }
+}
+
+impl MimeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, extension: &str, mime: &str) {
+        self.types.insert(
+            extension.trim_start_matches('.').to_ascii_lowercase(),
+            mime.into(),
+        );
+    }
+
+    pub fn set_fallback(&mut self, mime: &str) {
+        self.fallback = mime.into();
+    }
+
+    pub fn get(&self, extension: &str) -> Option<&str> {
+        self.types
+            .get(&extension.to_ascii_lowercase())
+            .map(|x| x.as_ref())
+    }
+
+    pub fn for_path(&self, path: &Path) -> &str {
+        path.extension()
+            .and_then(|x| x.to_str())
+            .and_then(|x| self.get(x))
+            .unwrap_or(&self.fallback)
+    }
+}
+
+#[test]
+fn mime_tests() {
+    let mut reg = MimeRegistry::new();
+    assert_eq!(
+        reg.for_path(Path::new("a/b.CSS")),
+        "text/css; charset=UTF-8"
+    );
+    assert_eq!(reg.for_path(Path::new("app.wasm")), "application/wasm");
+    assert_eq!(
+        reg.for_path(Path::new("README")),
+        "application/octet-stream"
+    );
+    reg.insert(".rs", "text/rust");
+    assert_eq!(reg.for_path(Path::new("main.rs")), "text/rust");
+}
@@ -0,0 +1,1778 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        TcpListener, TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::Mutex,
+};
+
+use crate::{
+    Exception, Throw, Throws,
+    events::{
+        BPipe, Daemon, DaemonId, Event, EventForwarder, EventHandler, EventSync, EventType,
+        HttpRequestId, TcpConnectionId, ThreadSafeIsh,
+    },
+    throw,
+};
+
+pub mod body;
+pub mod client;
+pub mod compress;
+pub mod files;
+pub mod mime;
+pub mod router;
+pub mod websocket;
+pub use body::{BodySource, BodyStream};
+use body::{ChunkedBody, LengthBody, read_chunked_body, write_chunk, write_last_chunk};
+pub use client::HttpClient;
+use compress::{ContentEncoding, EncodedCache, compress_response, negotiate_encoding};
+use files::{error_page, serve_static};
+pub use mime::MimeRegistry;
+pub use router::{RouteHandler, RouteRequest, Router};
+use router::{parse_query, split_target};
+use websocket::{
+    WsAssembler, WsFrame, WsMessage, is_websocket_upgrade, read_frame, websocket_handshake,
+    write_frame,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HttpMethod {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Connect => "CONNECT",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Patch => "PATCH",
+        }
+    }
+
+    /*
+     * whether sending the request twice has the same effect as sending it once
+     * */
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            HttpMethod::Get
+                | HttpMethod::Head
+                | HttpMethod::Put
+                | HttpMethod::Delete
+                | HttpMethod::Options
+                | HttpMethod::Trace
+        )
+    }
+}
+
+impl FromStr for HttpMethod {
+    type Err = Exception;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "GET" => HttpMethod::Get,
+            "HEAD" => HttpMethod::Head,
+            "POST" => HttpMethod::Post,
+            "PUT" => HttpMethod::Put,
+            "DELETE" => HttpMethod::Delete,
+            "CONNECT" => HttpMethod::Connect,
+            "OPTIONS" => HttpMethod::Options,
+            "TRACE" => HttpMethod::Trace,
+            "PATCH" => HttpMethod::Patch,
+            _ => {
+                throw!(format!("error unknown argument to http request:{:#?}", s));
+            }
+        })
+    }
+}
+
+impl Display for HttpMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+impl FromStr for HttpVersion {
+    type Err = Exception;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "HTTP/1.0" => HttpVersion::Http10,
+            "HTTP/1.1" => HttpVersion::Http11,
+            _ => {
+                throw!(format!("unsupported http version:{:#?}", s));
+            }
+        })
+    }
+}
+
+impl Display for HttpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpVersion::Http10 => write!(f, "HTTP/1.0"),
+            HttpVersion::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+/*
+* header names are compared case insensitively, insertion order is kept so a response goes out
+* the way it was built
+* */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HttpHeaders {
+    entries: Vec<(Arc<str>, Arc<str>)>,
+}
+
+impl HttpHeaders {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /*
+     * true if any comma separated element of the header equals token, ignoring case
+     * */
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn insert(&mut self, name: impl Into<Arc<str>>, value: impl Into<Arc<str>>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    pub fn append(&mut self, name: impl Into<Arc<str>>, value: impl Into<Arc<str>>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_ref(), v.as_ref()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/*
+* HTTP request, we will accept a body in any request for compatability with dumbshit because I
+* am a good girl. bodies too large to buffer come through as a stream and leave msg empty
+* */
+#[derive(Debug, Clone)]
+pub struct HTTPRequest {
+    pub method: HttpMethod,
+    pub target: Arc<str>,
+    pub path: Arc<str>,
+    pub query: BTreeMap<String, String>,
+    pub version: HttpVersion,
+    pub headers: HttpHeaders,
+    pub msg: Arc<[u8]>,
+    pub body: Option<BodyStream>,
+}
+
+impl HTTPRequest {
+    pub fn new(method: HttpMethod, target: &str) -> Self {
+        let (path, query) = split_target(target);
+        Self {
+            method,
+            target: target.into(),
+            path: path.into(),
+            query: parse_query(query),
+            version: HttpVersion::Http11,
+            headers: HttpHeaders::new(),
+            msg: Arc::new([]),
+            body: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<Arc<str>>, value: impl Into<Arc<str>>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, data: impl Into<Arc<[u8]>>) -> Self {
+        self.msg = data.into();
+        self.body = None;
+        self
+    }
+
+    pub fn with_stream(mut self, stream: BodyStream) -> Self {
+        self.msg = Arc::new([]);
+        self.body = Some(stream);
+        self
+    }
+
+    pub fn method(&self) -> HttpMethod {
+        self.method
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn msg(&self) -> &Arc<[u8]> {
+        &self.msg
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            HttpVersion::Http10 => self.headers.has_token("Connection", "keep-alive"),
+            HttpVersion::Http11 => !self.headers.has_token("Connection", "close"),
+        }
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(|x| x.as_str())
+    }
+
+    /*
+     * the whole body regardless of whether it was buffered or streamed, a streamed body can only
+     * be read once
+     * */
+    pub async fn read_body(&self) -> Throws<Arc<[u8]>> {
+        match &self.body {
+            Some(body) => body.collect().await,
+            None => Ok(self.msg.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HttpResponseType {
+    Text,
+    Html,
+    Png,
+    Jpeg,
+    Json,
+    Js,
+}
+
+impl HttpResponseType {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            HttpResponseType::Text => "text/plain; charset=UTF-8",
+            HttpResponseType::Html => "text/html; charset=UTF-8",
+            HttpResponseType::Png => "image/png",
+            HttpResponseType::Jpeg => "image/jpeg",
+            HttpResponseType::Json => "application/json; charset=UTF-8",
+            HttpResponseType::Js => "text/javascript; charset=UTF-8",
+        }
+    }
+}
+
+/*
+* if stream is set it is sent instead of data, with a Content-Length when the stream knows its
+* length and chunked otherwise
+* */
+#[derive(Debug, Clone)]
+pub struct HTTPResponse {
+    pub status: u16,
+    pub reason: Arc<str>,
+    pub headers: HttpHeaders,
+    pub data: Arc<[u8]>,
+    pub stream: Option<BodyStream>,
+}
+
+impl HTTPResponse {
+    pub fn new(response_type: HttpResponseType, data: impl Into<Arc<[u8]>>) -> Self {
+        Self::status(200)
+            .with_header("Content-Type", response_type.content_type())
+            .with_body(data)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            reason: reason_phrase(status).into(),
+            headers: HttpHeaders::new(),
+            data: Arc::new([]),
+            stream: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<Arc<str>>, value: impl Into<Arc<str>>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, data: impl Into<Arc<[u8]>>) -> Self {
+        self.data = data.into();
+        self.stream = None;
+        self
+    }
+
+    pub fn with_stream(mut self, stream: BodyStream) -> Self {
+        self.data = Arc::new([]);
+        self.stream = Some(stream);
+        self
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.headers.has_token("Transfer-Encoding", "chunked")
+            || (self
+                .stream
+                .as_ref()
+                .is_some_and(|x| x.content_length().is_none())
+                && !self.headers.contains("Content-Length"))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        426 => "Upgrade Required",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+/*
+* status line and headers, Content-Length is filled in from the body unless the response already
+* carries one or has a status that never has a body (1xx, 204, 304)
+* */
+pub fn http_response_head(response: &HTTPResponse) -> String {
+    let mut out = format!(
+        "{} {} {}\r\n",
+        HttpVersion::Http11,
+        response.status,
+        response.reason
+    );
+    for (k, v) in response.headers.iter() {
+        out += &format!("{}: {}\r\n", k, v);
+    }
+    let bodyless =
+        (100..200).contains(&response.status) || response.status == 204 || response.status == 304;
+    if bodyless {
+        out += "\r\n";
+        return out;
+    }
+    if response.is_chunked() {
+        if !response.headers.contains("Transfer-Encoding") {
+            out += "Transfer-Encoding: chunked\r\n";
+        }
+    } else if !response.headers.contains("Content-Length") {
+        let len = match &response.stream {
+            Some(stream) => stream.content_length().unwrap_or(0),
+            None => response.data.len() as u64,
+        };
+        out += &format!("Content-Length: {}\r\n", len);
+    }
+    out += "\r\n";
+    out
+}
+
+pub async fn http_write_response<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    response: &HTTPResponse,
+) -> Throws<()> {
+    stream
+        .write_all(http_response_head(response).as_bytes())
+        .await?;
+    let chunked = response.is_chunked();
+    match &response.stream {
+        Some(body) => {
+            while let Some(chunk) = body.next_chunk().await? {
+                if chunked {
+                    write_chunk(stream, &chunk).await?;
+                } else {
+                    stream.write_all(&chunk).await?;
+                }
+            }
+        }
+        None => {
+            if chunked {
+                write_chunk(stream, &response.data).await?;
+            } else {
+                stream.write_all(&response.data).await?;
+            }
+        }
+    }
+    if chunked {
+        write_last_chunk(stream).await?;
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+pub async fn http_write_response_head<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    response: &HTTPResponse,
+) -> Throws<()> {
+    stream
+        .write_all(http_response_head(response).as_bytes())
+        .await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+pub fn get_extension(s: &str) -> &str {
+    let split = s.split('.');
+    let mut last = None;
+    for i in split {
+        last = Some(i);
+    }
+    let ext = if let Some(e) = last { e } else { "" };
+    ext
+}
+
+#[test]
+fn extension_tests() {
+    assert_eq!(get_extension("test.png"), "png");
+    assert_eq!(get_extension("test.bak.png"), "png");
+    assert_eq!(get_extension("test.jpeg"), "jpeg");
+    assert_eq!(get_extension("test.bak.jpeg"), "jpeg");
+}
+
+pub const DEFAULT_MAX_LINE: usize = 8 * 1024;
+pub const DEFAULT_MAX_HEADERS: usize = 100;
+
+/*
+* the longest request, status or header line and the most header lines the parsers will read
+* before giving up on a message
+* */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadLimits {
+    pub max_line: usize,
+    pub max_headers: usize,
+}
+
+impl Default for HeadLimits {
+    fn default() -> Self {
+        Self {
+            max_line: DEFAULT_MAX_LINE,
+            max_headers: DEFAULT_MAX_HEADERS,
+        }
+    }
+}
+
+/*
+* lines longer than max bytes are refused, None means the stream ended before anything was read
+* so an empty line can be told apart from a closed connection
+* */
+pub async fn read_line<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max: usize,
+) -> Throws<Option<Vec<u8>>> {
+    let mut idx = 0;
+    let mut tbuf = [0];
+    let mut buf = [0; 256];
+    let mut out = Vec::new();
+    let mut read_any = false;
+    loop {
+        let x = stream.read(&mut tbuf).await?;
+        if x == 0 {
+            if !read_any {
+                return Ok(None);
+            }
+            break;
+        }
+        read_any = true;
+        if tbuf[0] == b'\n' {
+            break;
+        }
+        if out.len() + idx >= max {
+            throw!(format!("line is over the limit of {} bytes", max));
+        }
+        buf[idx] = tbuf[0];
+        idx += 1;
+        if buf.len() <= idx {
+            for i in buf {
+                out.push(i);
+            }
+            idx = 0;
+        }
+    }
+    for i in 0..idx {
+        out.push(buf[i]);
+    }
+    if out.last() == Some(&b'\r') {
+        out.pop();
+    }
+    return Ok(Some(out));
+}
+
+/*
+* reads a whole request into memory, bodies longer than max_body are refused
+* */
+pub async fn http_get_request<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_body: u64,
+) -> Throws<HTTPRequest> {
+    loop {
+        let Some(header) = read_line(stream, DEFAULT_MAX_LINE).await? else {
+            throw!("connection closed before a request arrived");
+        };
+        let header_string = std::str::from_utf8(&header)?;
+        if header_string.trim().is_empty() {
+            continue;
+        }
+        return http_parse_request(header_string, stream, max_body).await;
+    }
+}
+
+pub async fn http_parse_headers<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    limits: HeadLimits,
+) -> Throws<HttpHeaders> {
+    let mut headers = HttpHeaders::new();
+    let mut count = 0;
+    loop {
+        let Some(s) = read_line(stream, limits.max_line).await? else {
+            throw!("connection closed in the middle of the headers");
+        };
+        let s = str::from_utf8(&s)?;
+        if s.is_empty() {
+            break;
+        }
+        count += 1;
+        if count > limits.max_headers {
+            throw!(format!("more than {} header lines", limits.max_headers));
+        }
+        let Some((name, value)) = s.split_once(':') else {
+            throw!(format!("malformed http header:{:#?}", s));
+        };
+        headers.append(name.trim(), value.trim());
+    }
+    Ok(headers)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    Empty,
+    Length(u64),
+    Chunked,
+}
+
+pub fn body_framing(headers: &HttpHeaders) -> Throws<BodyFraming> {
+    if headers.has_token("Transfer-Encoding", "chunked") {
+        return Ok(BodyFraming::Chunked);
+    }
+    match headers.get("Content-Length") {
+        Some(cl) => Ok(BodyFraming::Length(cl.trim().parse::<u64>()?)),
+        None => Ok(BodyFraming::Empty),
+    }
+}
+
+/*
+* the request line and headers, the body is left on the stream
+* */
+pub async fn http_parse_request_head<S: AsyncRead + Unpin>(
+    header_string: &str,
+    stream: &mut S,
+    limits: HeadLimits,
+) -> Throws<HTTPRequest> {
+    let mut l1 = header_string.split_ascii_whitespace();
+    let method = l1.next().throw()?.parse::<HttpMethod>()?;
+    let target = l1.next().throw()?;
+    let version = match l1.next() {
+        Some(v) => v.parse::<HttpVersion>()?,
+        None => HttpVersion::Http10,
+    };
+    let headers = http_parse_headers(stream, limits).await?;
+    let mut out = HTTPRequest::new(method, target);
+    out.version = version;
+    out.headers = headers;
+    Ok(out)
+}
+
+/*
+* the rest of a request after its first line, bodies longer than max_body are refused
+* */
+pub async fn http_parse_request<S: AsyncRead + Unpin>(
+    header_string: &str,
+    stream: &mut S,
+    max_body: u64,
+) -> Throws<HTTPRequest> {
+    let mut out = http_parse_request_head(header_string, stream, HeadLimits::default()).await?;
+    out.msg = match body_framing(&out.headers)? {
+        BodyFraming::Empty => Arc::new([]),
+        BodyFraming::Length(cl) if cl > max_body => {
+            throw!(format!(
+                "body of {} bytes is over the limit of {}",
+                cl, max_body
+            ));
+        }
+        BodyFraming::Length(cl) => {
+            let mut buf = vec![0; cl as usize];
+            stream.read_exact(&mut buf).await?;
+            buf.into()
+        }
+        BodyFraming::Chunked => read_chunked_body(stream, max_body).await?.into(),
+    };
+    Ok(out)
+}
+
+/*
+* like http_get_request but bodies that are chunked or longer than max_buffered are handed back
+* as a BodyStream over the shared reader instead of being read into memory, the caller has to
+* drain that stream before reading the next request. chunks larger than max_chunk are refused,
+* None means the peer closed the connection before another request started
+* */
+pub async fn http_read_request<R: AsyncRead + Unpin + ThreadSafeIsh>(
+    reader: &Arc<Mutex<R>>,
+    limits: HeadLimits,
+    max_buffered: u64,
+    max_chunk: u64,
+) -> Throws<Option<HTTPRequest>> {
+    let mut stream = reader.lock().await;
+    let mut out = loop {
+        let Some(header) = read_line(&mut *stream, limits.max_line).await? else {
+            return Ok(None);
+        };
+        let header_string = std::str::from_utf8(&header)?;
+        if header_string.trim().is_empty() {
+            continue;
+        }
+        break http_parse_request_head(header_string, &mut *stream, limits).await?;
+    };
+    match body_framing(&out.headers)? {
+        BodyFraming::Empty => {}
+        BodyFraming::Length(cl) if cl <= max_buffered => {
+            let mut buf = vec![0; cl as usize];
+            stream.read_exact(&mut buf).await?;
+            out.msg = buf.into();
+        }
+        BodyFraming::Length(cl) => {
+            out.body = Some(BodyStream::new(
+                LengthBody::new(reader.clone(), cl),
+                Some(cl),
+            ));
+        }
+        BodyFraming::Chunked => {
+            out.body = Some(BodyStream::new(
+                ChunkedBody::new(reader.clone(), max_chunk),
+                None,
+            ));
+        }
+    }
+    Ok(Some(out))
+}
+
+#[tokio::test]
+async fn parse_request_tests() {
+    let raw = b"POST /users/10?name=bob+smith HTTP/1.1\r\nHost: localhost\r\ncontent-length: 5\r\nCookie: a=b\r\n\r\nhello";
+    let mut stream = &raw[..];
+    let req = http_get_request(&mut stream, 1024).await.unwrap();
+    assert_eq!(req.method, HttpMethod::Post);
+    assert_eq!(req.version, HttpVersion::Http11);
+    assert_eq!(req.path.as_ref(), "/users/10");
+    assert_eq!(req.query("name"), Some("bob smith"));
+    assert_eq!(req.header("host"), Some("localhost"));
+    assert_eq!(req.header("Cookie"), Some("a=b"));
+    assert_eq!(req.msg.as_ref(), b"hello");
+    let mut stream = &raw[..];
+    assert!(http_get_request(&mut stream, 4).await.is_err());
+    let truncated = b"GET / HTTP/1.1\r\nHost: localhost\r\n";
+    assert!(http_get_request(&mut &truncated[..], 0).await.is_err());
+    assert!(http_get_request(&mut &b"\r\n"[..], 0).await.is_err());
+    let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(DEFAULT_MAX_LINE));
+    assert!(http_get_request(&mut long.as_bytes(), 0).await.is_err());
+    let many = format!("GET / HTTP/1.1\r\n{}\r\n", "A: b\r\n".repeat(3));
+    let limits = HeadLimits {
+        max_line: 64,
+        max_headers: 2,
+    };
+    let mut stream = many.as_bytes();
+    let first = read_line(&mut stream, 64).await.unwrap().unwrap();
+    let first = std::str::from_utf8(&first).unwrap();
+    assert!(
+        http_parse_request_head(first, &mut stream, limits)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn write_response_tests() {
+    let response =
+        HTTPResponse::new(HttpResponseType::Json, b"{}".to_vec()).with_header("Set-Cookie", "a=b");
+    let mut out = Vec::new();
+    http_write_response(&mut out, &response).await.unwrap();
+    assert_eq!(
+        std::str::from_utf8(&out).unwrap(),
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=UTF-8\r\nSet-Cookie: a=b\r\nContent-Length: 2\r\n\r\n{}"
+    );
+    let response = HTTPResponse::status(404);
+    let mut out = Vec::new();
+    http_write_response(&mut out, &response).await.unwrap();
+    assert_eq!(
+        std::str::from_utf8(&out).unwrap(),
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+    );
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub handle_gets_locally: bool,
+    pub serve_dir: String,
+    pub router: Arc<Router>,
+    pub max_buffered_body: u64,
+    pub max_chunk_size: u64,
+    pub head_limits: HeadLimits,
+    pub idle_timeout: Duration,
+    pub read_timeout: Duration,
+    pub websockets: bool,
+    pub mime_types: Arc<MimeRegistry>,
+    pub directory_listing: bool,
+    pub compression: bool,
+    pub compression_threshold: u64,
+    pub encoded_cache: Arc<EncodedCache>,
+    pub response_timeout: Duration,
+}
+
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct HttpServer<T: ThreadSafeIsh> {
+    events: EventSync<T>,
+    listener: TcpListener,
+    config: HttpConfig,
+    _forwarder: BPipe<T>,
+}
+
+#[async_trait]
+impl<T: ThreadSafeIsh + Clone> Daemon for HttpServer<T> {
+    async fn run(&mut self) {
+        loop {
+            let con =
+                tokio::time::timeout(Duration::from_millis(250), self.listener.accept()).await;
+            let Ok(con) = con else {
+                self.update().await;
+                continue;
+            };
+            match con {
+                Ok((con, addr)) => {
+                    let con_id = TcpConnectionId::next();
+                    _ = self
+                        .events
+                        .send_global(Event::NotifyNewTcpConnection { id: con_id })
+                        .await;
+                    let ev = self.events.clone();
+                    let cfg = self.config.clone();
+                    tokio::spawn(async move {
+                        Self::run_tcp_connection(con, con_id, addr, ev, cfg).await
+                    });
+                }
+                Err(e) => {
+                    println!("error:{}", e);
+                    self.update().await;
+                }
+            }
+        }
+    }
+}
+
+impl<T: ThreadSafeIsh + Clone> HttpServer<T> {
+    pub async fn try_new(addr: &str, config: HttpConfig, events: EventSync<T>) -> Throws<Self> {
+        let forwarder = EventForwarder::new(|_| true, events.clone()).await;
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self {
+            events,
+            listener,
+            config,
+            _forwarder: forwarder,
+        })
+    }
+
+    pub fn local_addr(&self) -> Throws<std::net::SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn update(&mut self) {}
+
+    pub async fn run_tcp_connection(
+        con: TcpStream,
+        con_id: TcpConnectionId,
+        addr: std::net::SocketAddr,
+        sync: EventSync<T>,
+        config: HttpConfig,
+    ) {
+        _ = addr;
+        let events = EventForwarder::new_globals(
+            |f| {
+                let tmp = f.get_type();
+                tmp == EventType::HttpResponse
+                    || tmp == EventType::NetOutput
+                    || tmp == EventType::RequestConnectionKill
+                    || tmp == EventType::WebSocketSend
+            },
+            sync.clone(),
+        )
+        .await;
+        let (reader, writer) = con.into_split();
+        let mut handler = TcpHandler {
+            events,
+            reader: Arc::new(Mutex::new(BufReader::new(reader))),
+            stream: writer,
+            con_id,
+            config,
+            sync: sync.clone(),
+            pending: VecDeque::new(),
+            closing: false,
+            upgrade: None,
+            websocket: false,
+        };
+        handler.run().await;
+    }
+}
+
+/*
+* responses go out in the order their requests came in, a forwarded request holds its place in
+* the queue until a subscriber answers it or its deadline passes
+* */
+enum PendingResponse {
+    Ready {
+        response: HTTPResponse,
+        head_only: bool,
+        close: bool,
+    },
+    Waiting {
+        request_id: HttpRequestId,
+        deadline: Instant,
+        head_only: bool,
+        close: bool,
+        encoding: Option<ContentEncoding>,
+    },
+}
+
+struct TcpHandler<T: ThreadSafeIsh + Clone> {
+    events: BPipe<Event<T>>,
+    reader: Arc<Mutex<BufReader<OwnedReadHalf>>>,
+    stream: OwnedWriteHalf,
+    con_id: TcpConnectionId,
+    config: HttpConfig,
+    sync: EventSync<T>,
+    pending: VecDeque<PendingResponse>,
+    closing: bool,
+    upgrade: Option<HTTPRequest>,
+    websocket: bool,
+}
+impl<T: ThreadSafeIsh + Clone> TcpHandler<T> {
+    /*
+     * returns true if the connection was asked to die
+     * */
+    async fn poll_events(&mut self) -> Throws<bool> {
+        loop {
+            let Some(ev) = self.events.recieve()? else {
+                break;
+            };
+            match ev {
+                Event::HttpResponse {
+                    request_id,
+                    response,
+                } => {
+                    let slot = self.pending.iter_mut().find(|x| {
+                        matches!(x, PendingResponse::Waiting { request_id: r, .. } if *r == request_id)
+                    });
+                    let Some(slot) = slot else {
+                        continue;
+                    };
+                    let PendingResponse::Waiting {
+                        head_only,
+                        close,
+                        encoding,
+                        ..
+                    } = *slot
+                    else {
+                        unreachable!()
+                    };
+                    *slot = PendingResponse::Ready {
+                        response: compress_response(
+                            response,
+                            encoding,
+                            self.config.compression_threshold,
+                        )?,
+                        head_only,
+                        close,
+                    };
+                }
+                Event::NetOutput { id, data } if id == self.con_id => {
+                    if self.websocket {
+                        self.write_websocket(&WsMessage::Binary(data)).await?;
+                    } else {
+                        self.stream.write_all(&data).await?;
+                    }
+                }
+                Event::WebSocketSend { id, message } if id == self.con_id && self.websocket => {
+                    self.write_websocket(&message).await?;
+                    if let WsMessage::Close(_) = message {
+                        return Ok(true);
+                    }
+                }
+                Event::RequestConnectionKill { id } if id == self.con_id => {
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
+
+    /*
+     * forwarded requests nobody answered in time get a 504 so the ones behind them can go out
+     * */
+    fn expire_waiting(&mut self) {
+        let now = Instant::now();
+        for slot in self.pending.iter_mut() {
+            let PendingResponse::Waiting {
+                request_id,
+                deadline,
+                head_only,
+                close,
+                ..
+            } = *slot
+            else {
+                continue;
+            };
+            if deadline > now {
+                continue;
+            }
+            println!(
+                "cond_id:{},request:{} timed out",
+                self.con_id.inner(),
+                request_id.inner()
+            );
+            *slot = PendingResponse::Ready {
+                response: error_page(504),
+                head_only,
+                close,
+            };
+        }
+    }
+
+    async fn flush_ready(&mut self) -> Throws<()> {
+        while let Some(PendingResponse::Ready { .. }) = self.pending.front() {
+            let Some(PendingResponse::Ready {
+                mut response,
+                head_only,
+                close,
+            }) = self.pending.pop_front()
+            else {
+                unreachable!()
+            };
+            if close {
+                response.headers.insert("Connection", "close");
+            } else if !response.headers.contains("Connection") {
+                response.headers.insert("Connection", "keep-alive");
+            }
+            if head_only {
+                http_write_response_head(&mut self.stream, &response).await?;
+            } else {
+                http_write_response(&mut self.stream, &response).await?;
+            }
+            if close {
+                self.closing = true;
+                self.pending.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * waits up to one poll interval for the next request to start arriving, Some(false) means
+     * the peer hung up
+     * */
+    async fn wait_readable(&mut self) -> Throws<Option<bool>> {
+        let mut reader = self.reader.lock().await;
+        match tokio::time::timeout(EVENT_POLL_INTERVAL, reader.fill_buf()).await {
+            Ok(Ok(buf)) => Ok(Some(!buf.is_empty())),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn serve(&mut self) -> Throws<()> {
+        let mut last_activity = Instant::now();
+        loop {
+            if self.poll_events().await? {
+                return Ok(());
+            }
+            self.expire_waiting();
+            self.flush_ready().await?;
+            if self.pending.is_empty()
+                && let Some(req) = self.upgrade.take()
+            {
+                return self.serve_websocket(req).await;
+            }
+            if self.closing || self.upgrade.is_some() {
+                if self.pending.is_empty() {
+                    return Ok(());
+                }
+                tokio::time::sleep(EVENT_POLL_INTERVAL).await;
+                continue;
+            }
+            match self.wait_readable().await? {
+                Some(true) => {}
+                Some(false) => {
+                    self.closing = true;
+                    continue;
+                }
+                None => {
+                    if self.pending.is_empty() && last_activity.elapsed() > self.config.idle_timeout
+                    {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            }
+            let req = tokio::time::timeout(
+                self.config.read_timeout,
+                http_read_request(
+                    &self.reader,
+                    self.config.head_limits,
+                    self.config.max_buffered_body,
+                    self.config.max_chunk_size,
+                ),
+            )
+            .await;
+            let req = match req {
+                Ok(Ok(Some(req))) => req,
+                Ok(Ok(None)) => {
+                    self.closing = true;
+                    continue;
+                }
+                Ok(Err(_)) => {
+                    self.queue(HTTPResponse::status(400), false, true);
+                    continue;
+                }
+                Err(_) => {
+                    self.queue(HTTPResponse::status(408), false, true);
+                    continue;
+                }
+            };
+            let body = req.body.clone();
+            self.handle_request(req).await?;
+            if let Some(body) = body
+                && !self.closing
+            {
+                body.drain().await?;
+            }
+            last_activity = Instant::now();
+        }
+    }
+
+    async fn run(&mut self) {
+        if let Err(x) = self.serve().await {
+            _ = self.sync.report_fault(x);
+        }
+        _ = self.stream.shutdown().await;
+        _ = self.sync.tcp_disconnect(self.con_id);
+    }
+
+    async fn write_websocket(&mut self, message: &WsMessage) -> Throws<()> {
+        write_frame(&mut self.stream, &WsFrame::from_message(message), None).await
+    }
+
+    async fn serve_websocket(&mut self, request: HTTPRequest) -> Throws<()> {
+        self.websocket = true;
+        self.sync
+            .send_global(Event::WebSocketOpen {
+                id: self.con_id,
+                request,
+            })
+            .await?;
+        let mut assembler = WsAssembler::new(self.config.max_buffered_body);
+        loop {
+            if self.poll_events().await? {
+                return Ok(());
+            }
+            match self.wait_readable().await? {
+                Some(true) => {}
+                Some(false) => return Ok(()),
+                None => continue,
+            }
+            let max_len = self.config.max_buffered_body;
+            let frame = tokio::time::timeout(self.config.read_timeout, async {
+                read_frame(&mut *self.reader.lock().await, max_len, true).await
+            })
+            .await;
+            let message = match frame {
+                Ok(Ok(frame)) => assembler.push(frame),
+                Ok(Err(x)) => Err(x),
+                Err(_) => Err("timed out reading websocket frame".into()),
+            };
+            let message = match message {
+                Ok(Some(message)) => message,
+                Ok(None) => continue,
+                Err(x) => {
+                    let close = WsMessage::Close(Some((1002, "protocol error".into())));
+                    _ = self.write_websocket(&close).await;
+                    return Err(x);
+                }
+            };
+            match message {
+                WsMessage::Ping(data) => {
+                    self.write_websocket(&WsMessage::Pong(data)).await?;
+                }
+                WsMessage::Pong(_) => {}
+                WsMessage::Close(_) => {
+                    self.write_websocket(&WsMessage::Close(None)).await?;
+                    return Ok(());
+                }
+                message => {
+                    self.sync
+                        .send_global(Event::WebSocketMessage {
+                            id: self.con_id,
+                            message,
+                        })
+                        .await?;
+                }
+            }
+        }
+    }
+
+    fn queue(&mut self, response: HTTPResponse, head_only: bool, close: bool) {
+        self.pending.push_back(PendingResponse::Ready {
+            response,
+            head_only,
+            close,
+        });
+    }
+
+    async fn handle_request(&mut self, req: HTTPRequest) -> Throws<()> {
+        let head_only = req.method == HttpMethod::Head;
+        let close = !req.keep_alive();
+        if self.config.websockets && is_websocket_upgrade(&req) {
+            match websocket_handshake(&req) {
+                Ok(response) => {
+                    self.queue(response, false, false);
+                    self.upgrade = Some(req);
+                }
+                Err(x) => {
+                    println!(
+                        "websocket handshake failed:{} line:{} file:{}",
+                        x.error, x.line, x.file
+                    );
+                    self.queue(HTTPResponse::status(400), false, true);
+                }
+            }
+            return Ok(());
+        }
+        if let Some(res) = self.config.router.dispatch(&req).await {
+            let response = match res {
+                Ok(response) => response,
+                Err(x) => {
+                    println!(
+                        "route threw exception:{} line:{} file:{}",
+                        x.error, x.line, x.file
+                    );
+                    HTTPResponse::status(500)
+                }
+            };
+            let encoding = self.config.negotiate_encoding(&req);
+            let response =
+                compress_response(response, encoding, self.config.compression_threshold)?;
+            self.queue(response, head_only, close);
+            return Ok(());
+        }
+        match req.method {
+            HttpMethod::Get | HttpMethod::Head if self.config.handle_gets_locally => {
+                let response = match serve_static(&self.config, &req).await {
+                    Ok(response) => response,
+                    Err(x) => {
+                        println!(
+                            "serving {} threw exception:{} line:{} file:{}",
+                            req.path, x.error, x.line, x.file
+                        );
+                        error_page(500)
+                    }
+                };
+                self.queue(response, head_only, close);
+            }
+            _ => {
+                self.forward_request(req).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * subscribers see the request after this connection has moved on, so a streamed body gets
+     * buffered before it goes out as an event. one longer than max_buffered_body gets a 413
+     * instead, and since the rest of it is still unread the connection is closed after that
+     * */
+    async fn forward_request(&mut self, mut req: HTTPRequest) -> Throws<()> {
+        if let Some(body) = req.body.take() {
+            let head_only = req.method == HttpMethod::Head;
+            match body.collect_limited(self.config.max_buffered_body).await {
+                Ok(Some(msg)) => req.msg = msg,
+                Ok(None) => {
+                    self.queue(error_page(413), head_only, true);
+                    self.closing = true;
+                    return Ok(());
+                }
+                Err(x) => {
+                    println!(
+                        "threw exception:{} line:{} file:{}",
+                        x.error, x.line, x.file
+                    );
+                    self.queue(HTTPResponse::status(400), head_only, true);
+                    self.closing = true;
+                    return Ok(());
+                }
+            }
+        }
+        let request_id = HttpRequestId::next();
+        self.pending.push_back(PendingResponse::Waiting {
+            request_id,
+            deadline: Instant::now() + self.config.response_timeout,
+            head_only: req.method == HttpMethod::Head,
+            close: !req.keep_alive(),
+            encoding: self.config.negotiate_encoding(&req),
+        });
+        let ev = Event::HttpRequest {
+            id: self.con_id,
+            request_id,
+            request: req,
+        };
+        self.sync.send_global(ev).await?;
+        Ok(())
+    }
+}
+
+pub struct HttpConfigBuilder {
+    handle_get_requests_locally: bool,
+    serve_dir: String,
+    router: Router,
+    max_buffered_body: u64,
+    max_chunk_size: u64,
+    head_limits: HeadLimits,
+    idle_timeout: Duration,
+    read_timeout: Duration,
+    websockets: bool,
+    mime_types: MimeRegistry,
+    directory_listing: bool,
+    compression: bool,
+    compression_threshold: u64,
+    compression_cache_size: usize,
+    response_timeout: Duration,
+}
+
+impl HttpConfig {
+    pub fn new() -> HttpConfigBuilder {
+        HttpConfigBuilder {
+            handle_get_requests_locally: true,
+            serve_dir: String::from("."),
+            router: Router::new(),
+            max_buffered_body: 1024 * 1024,
+            max_chunk_size: 16 * 1024 * 1024,
+            head_limits: HeadLimits::default(),
+            idle_timeout: Duration::from_secs(60),
+            read_timeout: Duration::from_secs(30),
+            websockets: false,
+            mime_types: MimeRegistry::new(),
+            directory_listing: false,
+            compression: true,
+            compression_threshold: 1024,
+            compression_cache_size: 32 * 1024 * 1024,
+            response_timeout: Duration::from_secs(30),
+        }
+    }
+    /*
+     * the encoding a response to this request should use, None when compression is off or the
+     * client did not ask for one we support
+     * */
+    pub fn negotiate_encoding(&self, request: &HTTPRequest) -> Option<ContentEncoding> {
+        if !self.compression {
+            return None;
+        }
+        request
+            .header("Accept-Encoding")
+            .and_then(negotiate_encoding)
+    }
+}
+
+impl HttpConfigBuilder {
+    pub fn build(self) -> HttpConfig {
+        HttpConfig {
+            serve_dir: self.serve_dir,
+            handle_gets_locally: self.handle_get_requests_locally,
+            router: Arc::new(self.router),
+            max_buffered_body: self.max_buffered_body,
+            max_chunk_size: self.max_chunk_size,
+            head_limits: self.head_limits,
+            idle_timeout: self.idle_timeout,
+            read_timeout: self.read_timeout,
+            websockets: self.websockets,
+            mime_types: Arc::new(self.mime_types),
+            directory_listing: self.directory_listing,
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            encoded_cache: Arc::new(EncodedCache::new(self.compression_cache_size)),
+            response_timeout: self.response_timeout,
+        }
+    }
+    pub fn forward_gets(mut self) -> Self {
+        self.handle_get_requests_locally = true;
+        self
+    }
+    pub fn serve_dir(mut self, dir: impl Into<String>) -> Self {
+        self.serve_dir = dir.into();
+        self
+    }
+    /*
+     * request bodies and served files larger than this are streamed instead of read into memory,
+     * routes get the stream but a request forwarded as an event this large is answered with 413
+     * */
+    pub fn max_buffered_body(mut self, bytes: u64) -> Self {
+        self.max_buffered_body = bytes;
+        self
+    }
+    /*
+     * the largest chunk size a client may announce in a chunked request body
+     * */
+    pub fn max_chunk_size(mut self, bytes: u64) -> Self {
+        self.max_chunk_size = bytes;
+        self
+    }
+    /*
+     * requests with a longer request or header line get a 400
+     * */
+    pub fn max_line_length(mut self, bytes: usize) -> Self {
+        self.head_limits.max_line = bytes;
+        self
+    }
+    /*
+     * requests with more header lines than this get a 400
+     * */
+    pub fn max_header_count(mut self, count: usize) -> Self {
+        self.head_limits.max_headers = count;
+        self
+    }
+    /*
+     * how long a keep-alive connection may sit between requests before it is closed
+     * */
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+    /*
+     * how long a forwarded request waits for EventSync::respond_http before the client gets a 504
+     * */
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = timeout;
+        self
+    }
+    /*
+     * how long a client gets to finish sending a request once it has started one
+     * */
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+    /*
+     * accept Upgrade: websocket requests, messages then show up as Event::WebSocketMessage and
+     * go back out through EventSync::websocket_send or as binary frames through NetOutput
+     * */
+    pub fn websockets(mut self, enabled: bool) -> Self {
+        self.websockets = enabled;
+        self
+    }
+    /*
+     * adds or overrides the Content-Type served for files with this extension
+     * */
+    pub fn mime_type(mut self, extension: &str, mime: &str) -> Self {
+        self.mime_types.insert(extension, mime);
+        self
+    }
+    /*
+     * serve a generated index for directories without an index.html instead of a 403
+     * */
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+    /*
+     * gzip/deflate buffered response bodies when the client sends a matching Accept-Encoding
+     * */
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+    /*
+     * bodies smaller than this are sent as they are
+     * */
+    pub fn compression_threshold(mut self, bytes: u64) -> Self {
+        self.compression_threshold = bytes;
+        self
+    }
+    /*
+     * how many bytes of encoded static files are kept around between requests
+     * */
+    pub fn compression_cache_size(mut self, bytes: usize) -> Self {
+        self.compression_cache_size = bytes;
+        self
+    }
+    /*
+     * requests that match no route fall through to the serve_dir/event forwarding path
+     * */
+    pub fn route(mut self, method: HttpMethod, pattern: &str, handler: impl RouteHandler) -> Self {
+        self.router.route(method, pattern, handler);
+        self
+    }
+    pub fn route_any(mut self, pattern: &str, handler: impl RouteHandler) -> Self {
+        self.router.any(pattern, handler);
+        self
+    }
+    pub fn get(self, pattern: &str, handler: impl RouteHandler) -> Self {
+        self.route(HttpMethod::Get, pattern, handler)
+    }
+    pub fn post(self, pattern: &str, handler: impl RouteHandler) -> Self {
+        self.route(HttpMethod::Post, pattern, handler)
+    }
+    pub fn put(self, pattern: &str, handler: impl RouteHandler) -> Self {
+        self.route(HttpMethod::Put, pattern, handler)
+    }
+    pub fn delete(self, pattern: &str, handler: impl RouteHandler) -> Self {
+        self.route(HttpMethod::Delete, pattern, handler)
+    }
+}
+
+#[tokio::test]
+async fn keep_alive_tests() {
+    let (sender, _handler) = EventHandler::<ServerEvent>::new();
+    let sync = EventSync::new(sender);
+    let config = HttpConfig::new()
+        .get("/echo/:word", async |req: RouteRequest| {
+            Ok(HTTPResponse::new(
+                HttpResponseType::Text,
+                req.param("word").unwrap().as_bytes().to_vec(),
+            ))
+        })
+        .build();
+    let mut server = HttpServer::try_new("127.0.0.1:0", config, sync)
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run().await });
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    con.write_all(
+        b"GET /echo/one HTTP/1.1\r\n\r\nGET /echo/two HTTP/1.1\r\nConnection: close\r\n\r\n",
+    )
+    .await
+    .unwrap();
+    let mut out = String::new();
+    tokio::time::timeout(Duration::from_secs(5), con.read_to_string(&mut out))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        out,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nConnection: keep-alive\r\nContent-Length: 3\r\n\r\none\
+        HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nConnection: close\r\nContent-Length: 3\r\n\r\ntwo"
+    );
+}
+
+#[tokio::test]
+async fn peer_close_tests() {
+    let (sender, _handler) = EventHandler::<ServerEvent>::new();
+    let config = HttpConfig::new().build();
+    let mut server = HttpServer::try_new("127.0.0.1:0", config, EventSync::new(sender))
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run().await });
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    con.write_all(b"\r\n").await.unwrap();
+    con.shutdown().await.unwrap();
+    let mut out = String::new();
+    tokio::time::timeout(Duration::from_secs(2), con.read_to_string(&mut out))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(out, "");
+}
+
+#[tokio::test]
+async fn websocket_upgrade_tests() {
+    let (sender, _handler) = EventHandler::<ServerEvent>::new();
+    let config = HttpConfig::new().websockets(true).build();
+    let mut server = HttpServer::try_new("127.0.0.1:0", config, EventSync::new(sender))
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run().await });
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    let upgrade = b"GET /live HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+    con.write_all(upgrade).await.unwrap();
+    let status = read_line(&mut con, DEFAULT_MAX_LINE)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(status, b"HTTP/1.1 101 Switching Protocols");
+    let headers = http_parse_headers(&mut con, HeadLimits::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        headers.get("Sec-WebSocket-Accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+    let ping = WsFrame::from_message(&WsMessage::Ping(Arc::new([1, 2])));
+    write_frame(&mut con, &ping, Some([1, 2, 3, 4]))
+        .await
+        .unwrap();
+    let pong = read_frame(&mut con, 1024, false).await.unwrap();
+    assert_eq!(
+        pong,
+        WsFrame::from_message(&WsMessage::Pong(Arc::new([1, 2])))
+    );
+    let close = WsFrame::from_message(&WsMessage::Close(None));
+    write_frame(&mut con, &close, Some([1, 2, 3, 4]))
+        .await
+        .unwrap();
+    assert_eq!(read_frame(&mut con, 1024, false).await.unwrap(), close);
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    con.write_all(upgrade).await.unwrap();
+    let status = read_line(&mut con, DEFAULT_MAX_LINE)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(status, b"HTTP/1.1 101 Switching Protocols");
+    http_parse_headers(&mut con, HeadLimits::default())
+        .await
+        .unwrap();
+    let text = WsFrame::from_message(&WsMessage::Text("unmasked".into()));
+    write_frame(&mut con, &text, None).await.unwrap();
+    assert_eq!(
+        read_frame(&mut con, 1024, false).await.unwrap(),
+        WsFrame::from_message(&WsMessage::Close(Some((1002, "protocol error".into()))))
+    );
+}
+
+#[tokio::test]
+async fn forwarded_request_tests() {
+    let (sender, mut handler) = EventHandler::<ServerEvent>::new();
+    let sync = EventSync::new(sender);
+    let config = HttpConfig::new()
+        .response_timeout(Duration::from_millis(300))
+        .build();
+    let mut server = HttpServer::try_new("127.0.0.1:0", config, sync.clone())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let requests =
+        EventForwarder::new_globals(|e| e.get_type() == EventType::HttpRequest, sync.clone()).await;
+    tokio::spawn(async move { server.run().await });
+    tokio::spawn(async move {
+        loop {
+            handler.handle_events().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    });
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    con.write_all(b"POST /a HTTP/1.1\r\nContent-Length: 0\r\n\r\nPOST /b HTTP/1.1\r\nContent-Length: 0\r\n\r\nPOST /c HTTP/1.1\r\nConnection: close\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+    let mut seen = Vec::new();
+    while seen.len() < 3 {
+        match requests.recieve().unwrap() {
+            Some(Event::HttpRequest {
+                request_id,
+                request,
+                ..
+            }) => seen.push((request_id, request.path.clone())),
+            _ => tokio::time::sleep(Duration::from_millis(1)).await,
+        }
+    }
+    for (request_id, path) in seen.iter().rev() {
+        if path.as_ref() != "/c" {
+            let response = HTTPResponse::new(HttpResponseType::Text, path.as_bytes().to_vec());
+            sync.respond_http(*request_id, response).unwrap();
+        }
+    }
+    let mut out = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), con.read_to_end(&mut out))
+        .await
+        .unwrap()
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let a = out.find("\r\n\r\n/a").unwrap();
+    let b = out.find("\r\n\r\n/b").unwrap();
+    let timeout = out.find("HTTP/1.1 504 Gateway Timeout").unwrap();
+    assert!(a < b && b < timeout);
+}
+
+#[tokio::test]
+async fn unreadable_file_tests() {
+    let root = std::env::temp_dir().join(format!("rtils_unreadable_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("ok.txt"), "fine").unwrap();
+    let _socket = std::os::unix::net::UnixListener::bind(root.join("socket")).unwrap();
+    let (sender, _handler) = EventHandler::<ServerEvent>::new();
+    let config = HttpConfig::new().serve_dir(root.to_str().unwrap()).build();
+    let mut server = HttpServer::try_new("127.0.0.1:0", config, EventSync::new(sender))
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run().await });
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    con.write_all(b"GET /socket HTTP/1.1\r\n\r\nGET /ok.txt HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut out = String::new();
+    tokio::time::timeout(Duration::from_secs(5), con.read_to_string(&mut out))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(out.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    assert!(out.ends_with("\r\n\r\nfine"));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn oversized_upload_tests() {
+    let (sender, _handler) = EventHandler::<ServerEvent>::new();
+    let config = HttpConfig::new()
+        .max_buffered_body(8)
+        .max_chunk_size(16)
+        .build();
+    let mut server = HttpServer::try_new("127.0.0.1:0", config, EventSync::new(sender))
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run().await });
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    con.write_all(
+        b"POST /up HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nabcdef\r\n6\r\nghijkl\r\n",
+    )
+    .await
+    .unwrap();
+    let mut out = String::new();
+    tokio::time::timeout(Duration::from_secs(5), con.read_to_string(&mut out))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(out.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+    assert!(out.contains("Connection: close\r\n"));
+    let mut con = TcpStream::connect(addr).await.unwrap();
+    con.write_all(b"POST /up HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffff\r\n")
+        .await
+        .unwrap();
+    let mut out = String::new();
+    tokio::time::timeout(Duration::from_secs(5), con.read_to_string(&mut out))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerEvent {}
+
+pub async fn file_server(serve_dir: &str, addr: String) {
+    let (_sender, mut handler) = EventHandler::<ServerEvent>::new();
+    let dir = serve_dir.to_string();
+    if let Err(x) = handler
+        .run(async move |sync| file_server_setup(sync, dir.clone(), addr.clone()).await)
+        .await
+    {
+        println!(
+            "file server threw exception:{} line:{} file:{}",
+            x.error, x.line, x.file
+        );
+    }
+}
+
+pub async fn file_server_setup(sync: EventSync<ServerEvent>, serve_dir: String, addr: String) {
+    let config = HttpConfig::new().serve_dir(serve_dir).build();
+    let server = HttpServer::try_new(&addr, config, sync.clone())
+        .await
+        .unwrap();
+    sync.new_event_global(Event::CreateDaemon {
+        daemon: Box::new(server),
+        id: DaemonId::alloc(),
+    })
+    .unwrap();
+}
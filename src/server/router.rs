@@ -0,0 +1,288 @@
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{
+    Throws,
+    events::ThreadSafeIsh,
+    server::{HTTPRequest, HTTPResponse, HttpMethod},
+};
+
+/*
+* a request that matched a route, params are the captured `:name` and `*name` segments of the
+* path
+* */
+#[derive(Debug, Clone)]
+pub struct RouteRequest {
+    pub request: HTTPRequest,
+    pub path: Arc<str>,
+    pub params: BTreeMap<String, String>,
+    pub query: BTreeMap<String, String>,
+}
+
+impl RouteRequest {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|x| x.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(|x| x.as_str())
+    }
+}
+
+#[async_trait]
+pub trait RouteHandler: ThreadSafeIsh {
+    async fn handle(&self, request: RouteRequest) -> Throws<HTTPResponse>;
+}
+
+#[async_trait]
+impl<F, Fut> RouteHandler for F
+where
+    F: Fn(RouteRequest) -> Fut + ThreadSafeIsh,
+    Fut: Future<Output = Throws<HTTPResponse>> + Send,
+{
+    async fn handle(&self, request: RouteRequest) -> Throws<HTTPResponse> {
+        (self)(request).await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(Option<String>),
+}
+
+/*
+* patterns look like /users/:id/posts or /static/ followed by *path, a wildcard has to be the
+* last segment and swallows the rest of the path (including slashes)
+* */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutePattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    pub fn parse(pattern: &str) -> Self {
+        let mut segments = Vec::new();
+        for seg in pattern.split('/').filter(|s| !s.is_empty()) {
+            if let Some(name) = seg.strip_prefix(':') {
+                segments.push(Segment::Param(name.to_string()));
+            } else if let Some(name) = seg.strip_prefix('*') {
+                if name.is_empty() {
+                    segments.push(Segment::Wildcard(None));
+                } else {
+                    segments.push(Segment::Wildcard(Some(name.to_string())));
+                }
+                break;
+            } else {
+                segments.push(Segment::Literal(seg.to_string()));
+            }
+        }
+        Self {
+            source: pattern.to_string(),
+            segments,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, path: &str) -> Option<BTreeMap<String, String>> {
+        let mut params = BTreeMap::new();
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut idx = 0;
+        for seg in &self.segments {
+            match seg {
+                Segment::Literal(lit) => {
+                    let part = parts.get(idx)?;
+                    if percent_decode(part) != *lit {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let part = parts.get(idx)?;
+                    params.insert(name.clone(), percent_decode(part));
+                }
+                Segment::Wildcard(name) => {
+                    if let Some(name) = name {
+                        let rest: Vec<String> =
+                            parts[idx..].iter().map(|x| percent_decode(x)).collect();
+                        params.insert(name.clone(), rest.join("/"));
+                    }
+                    return Some(params);
+                }
+            }
+            idx += 1;
+        }
+        if idx != parts.len() {
+            return None;
+        }
+        Some(params)
+    }
+}
+
+struct Route {
+    method: Option<HttpMethod>,
+    pattern: RoutePattern,
+    handler: Arc<dyn RouteHandler>,
+}
+
+/*
+* routes are tried in the order they were registered, the first match wins
+* */
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<Arc<Route>>,
+}
+
+impl Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut list = f.debug_list();
+        for r in &self.routes {
+            list.entry(&(r.method, r.pattern.source()));
+        }
+        list.finish()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn route(&mut self, method: HttpMethod, pattern: &str, handler: impl RouteHandler) {
+        self.routes.push(Arc::new(Route {
+            method: Some(method),
+            pattern: RoutePattern::parse(pattern),
+            handler: Arc::new(handler),
+        }));
+    }
+
+    pub fn any(&mut self, pattern: &str, handler: impl RouteHandler) {
+        self.routes.push(Arc::new(Route {
+            method: None,
+            pattern: RoutePattern::parse(pattern),
+            handler: Arc::new(handler),
+        }));
+    }
+
+    pub fn find(&self, request: &HTTPRequest) -> Option<(Arc<dyn RouteHandler>, RouteRequest)> {
+        let path = request.path.as_ref();
+        let method = request.method();
+        for r in &self.routes {
+            if r.method.is_some_and(|m| m != method) {
+                continue;
+            }
+            let Some(params) = r.pattern.matches(path) else {
+                continue;
+            };
+            let req = RouteRequest {
+                request: request.clone(),
+                path: request.path.clone(),
+                params,
+                query: request.query.clone(),
+            };
+            return Some((r.handler.clone(), req));
+        }
+        None
+    }
+
+    pub async fn dispatch(&self, request: &HTTPRequest) -> Option<Throws<HTTPResponse>> {
+        let (handler, req) = self.find(request)?;
+        Some(handler.handle(req).await)
+    }
+}
+
+pub fn split_target(target: &str) -> (&str, &str) {
+    let target = target.split_once('#').map(|x| x.0).unwrap_or(target);
+    target.split_once('?').unwrap_or((target, ""))
+}
+
+pub fn parse_query(query: &str) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    for pair in query.split('&').filter(|s| !s.is_empty()) {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        out.insert(
+            percent_decode(&k.replace('+', " ")),
+            percent_decode(&v.replace('+', " ")),
+        );
+    }
+    out
+}
+
+pub fn percent_decode(s: &str) -> String {
+    fn hex(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
+        }
+    }
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let (Some(h), Some(l)) = (hex(bytes[i + 1]), hex(bytes[i + 2]))
+        {
+            out.push(h * 16 + l);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/*
+* encodes everything outside of the unreserved set, for building a single path segment
+* */
+pub fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out += &format!("%{:02X}", b),
+        }
+    }
+    out
+}
+
+#[test]
+fn route_pattern_tests() {
+    let p = RoutePattern::parse("/users/:id");
+    assert_eq!(p.matches("/users/10").unwrap()["id"], "10");
+    assert!(p.matches("/users").is_none());
+    assert!(p.matches("/users/10/posts").is_none());
+    let p = RoutePattern::parse("/static/*path");
+    assert_eq!(p.matches("/static/img/a.png").unwrap()["path"], "img/a.png");
+    assert_eq!(p.matches("/static").unwrap()["path"], "");
+    let p = RoutePattern::parse("/");
+    assert!(p.matches("/").is_some());
+    assert!(p.matches("/a").is_none());
+}
+
+#[test]
+fn query_tests() {
+    let (path, query) = split_target("/search?q=hello+world&page=2&flag");
+    assert_eq!(path, "/search");
+    let q = parse_query(query);
+    assert_eq!(q["q"], "hello world");
+    assert_eq!(q["page"], "2");
+    assert_eq!(q["flag"], "");
+    assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_encode("a b/c.txt"), "a%20b%2Fc.txt");
+}
@@ -0,0 +1,343 @@
+use std::sync::Arc;
+
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    Exception, Throws,
+    server::{HTTPRequest, HTTPResponse, HttpMethod},
+    throw,
+};
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WsMessage {
+    Text(Arc<str>),
+    Binary(Arc<[u8]>),
+    Ping(Arc<[u8]>),
+    Pong(Arc<[u8]>),
+    Close(Option<(u16, Arc<str>)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsOpcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl WsOpcode {
+    pub fn from_u8(v: u8) -> Throws<Self> {
+        Ok(match v {
+            0x0 => WsOpcode::Continuation,
+            0x1 => WsOpcode::Text,
+            0x2 => WsOpcode::Binary,
+            0x8 => WsOpcode::Close,
+            0x9 => WsOpcode::Ping,
+            0xA => WsOpcode::Pong,
+            _ => {
+                throw!(format!("unknown websocket opcode:{:#x}", v));
+            }
+        })
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            WsOpcode::Continuation => 0x0,
+            WsOpcode::Text => 0x1,
+            WsOpcode::Binary => 0x2,
+            WsOpcode::Close => 0x8,
+            WsOpcode::Ping => 0x9,
+            WsOpcode::Pong => 0xA,
+        }
+    }
+
+    pub fn is_control(&self) -> bool {
+        matches!(self, WsOpcode::Close | WsOpcode::Ping | WsOpcode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WsFrame {
+    pub fin: bool,
+    pub opcode: WsOpcode,
+    pub payload: Vec<u8>,
+}
+
+impl WsFrame {
+    pub fn from_message(message: &WsMessage) -> Self {
+        let (opcode, payload) = match message {
+            WsMessage::Text(s) => (WsOpcode::Text, s.as_bytes().to_vec()),
+            WsMessage::Binary(b) => (WsOpcode::Binary, b.to_vec()),
+            WsMessage::Ping(b) => (WsOpcode::Ping, b.to_vec()),
+            WsMessage::Pong(b) => (WsOpcode::Pong, b.to_vec()),
+            WsMessage::Close(None) => (WsOpcode::Close, Vec::new()),
+            WsMessage::Close(Some((code, reason))) => {
+                let mut out = code.to_be_bytes().to_vec();
+                out.extend_from_slice(reason.as_bytes());
+                (WsOpcode::Close, out)
+            }
+        };
+        Self {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+}
+
+pub fn is_websocket_upgrade(request: &HTTPRequest) -> bool {
+    request.method == HttpMethod::Get
+        && request.headers.has_token("Connection", "upgrade")
+        && request.headers.has_token("Upgrade", "websocket")
+}
+
+pub fn websocket_accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+pub fn websocket_handshake(request: &HTTPRequest) -> Throws<HTTPResponse> {
+    let Some(key) = request.header("Sec-WebSocket-Key") else {
+        throw!("websocket upgrade without Sec-WebSocket-Key");
+    };
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        throw!("unsupported websocket version");
+    }
+    Ok(HTTPResponse::status(101)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", websocket_accept_key(key)))
+}
+
+/*
+* payloads longer than max_len are rejected before they are read and the mask is undone here,
+* servers pass require_mask since client frames without one are a protocol error
+* */
+pub async fn read_frame<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_len: u64,
+    require_mask: bool,
+) -> Throws<WsFrame> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).await?;
+    let fin = head[0] & 0x80 != 0;
+    if head[0] & 0x70 != 0 {
+        throw!("websocket frame uses reserved bits");
+    }
+    let opcode = WsOpcode::from_u8(head[0] & 0x0F)?;
+    let masked = head[1] & 0x80 != 0;
+    if require_mask && !masked {
+        throw!("client websocket frame is not masked");
+    }
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut buf = [0; 2];
+            stream.read_exact(&mut buf).await?;
+            u16::from_be_bytes(buf) as u64
+        }
+        127 => {
+            let mut buf = [0; 8];
+            stream.read_exact(&mut buf).await?;
+            u64::from_be_bytes(buf)
+        }
+        x => x as u64,
+    };
+    if opcode.is_control() && (len > 125 || !fin) {
+        throw!("malformed websocket control frame");
+    }
+    if len > max_len {
+        throw!(format!("websocket frame of {} bytes is too large", len));
+    }
+    let mut mask = [0; 4];
+    if masked {
+        stream.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload).await?;
+    if masked {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+    Ok(WsFrame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/*
+* servers send unmasked frames, clients have to pass a mask
+* */
+pub async fn write_frame<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    frame: &WsFrame,
+    mask: Option<[u8; 4]>,
+) -> Throws<()> {
+    let mut out = Vec::with_capacity(frame.payload.len() + 14);
+    out.push(if frame.fin { 0x80 } else { 0 } | frame.opcode.as_u8());
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let len = frame.payload.len();
+    if len < 126 {
+        out.push(mask_bit | len as u8);
+    } else if len <= u16::MAX as usize {
+        out.push(mask_bit | 126);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(mask_bit | 127);
+        out.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    match mask {
+        Some(mask) => {
+            out.extend_from_slice(&mask);
+            out.extend(
+                frame
+                    .payload
+                    .iter()
+                    .enumerate()
+                    .map(|(i, b)| b ^ mask[i % 4]),
+            );
+        }
+        None => out.extend_from_slice(&frame.payload),
+    }
+    stream.write_all(&out).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/*
+* stitches fragmented frames back into messages, control frames may arrive in the middle of a
+* fragmented message and are passed straight through
+* */
+pub struct WsAssembler {
+    opcode: Option<WsOpcode>,
+    buffer: Vec<u8>,
+    max_len: u64,
+}
+
+impl WsAssembler {
+    pub fn new(max_len: u64) -> Self {
+        Self {
+            opcode: None,
+            buffer: Vec::new(),
+            max_len,
+        }
+    }
+
+    pub fn push(&mut self, frame: WsFrame) -> Throws<Option<WsMessage>> {
+        if frame.opcode.is_control() {
+            return Ok(Some(Self::to_message(frame.opcode, frame.payload)?));
+        }
+        match (frame.opcode, self.opcode) {
+            (WsOpcode::Continuation, None) => {
+                throw!("websocket continuation frame without a message to continue");
+            }
+            (WsOpcode::Continuation, Some(_)) => {}
+            (op, None) => {
+                self.opcode = Some(op);
+            }
+            (_, Some(_)) => {
+                throw!("websocket message started before the previous one finished");
+            }
+        }
+        if (self.buffer.len() + frame.payload.len()) as u64 > self.max_len {
+            throw!("websocket message is too large");
+        }
+        self.buffer.extend_from_slice(&frame.payload);
+        if !frame.fin {
+            return Ok(None);
+        }
+        let opcode = self.opcode.take().unwrap_or(WsOpcode::Binary);
+        let payload = std::mem::take(&mut self.buffer);
+        Ok(Some(Self::to_message(opcode, payload)?))
+    }
+
+    fn to_message(opcode: WsOpcode, payload: Vec<u8>) -> Throws<WsMessage> {
+        Ok(match opcode {
+            WsOpcode::Text => WsMessage::Text(String::from_utf8(payload)?.into()),
+            WsOpcode::Binary | WsOpcode::Continuation => WsMessage::Binary(payload.into()),
+            WsOpcode::Ping => WsMessage::Ping(payload.into()),
+            WsOpcode::Pong => WsMessage::Pong(payload.into()),
+            WsOpcode::Close => {
+                if payload.len() < 2 {
+                    WsMessage::Close(None)
+                } else {
+                    let code = u16::from_be_bytes([payload[0], payload[1]]);
+                    let reason = String::from_utf8_lossy(&payload[2..]);
+                    WsMessage::Close(Some((code, reason.into())))
+                }
+            }
+        })
+    }
+}
+
+#[test]
+fn websocket_accept_key_tests() {
+    assert_eq!(
+        websocket_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[tokio::test]
+async fn websocket_frame_tests() {
+    let mut out = Vec::new();
+    let frame = WsFrame::from_message(&WsMessage::Text("Hello".into()));
+    write_frame(&mut out, &frame, Some([0x37, 0xfa, 0x21, 0x3d]))
+        .await
+        .unwrap();
+    assert_eq!(
+        out,
+        [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58
+        ]
+    );
+    let mut stream = &out[..];
+    assert_eq!(read_frame(&mut stream, 1024, true).await.unwrap(), frame);
+    let big = WsFrame {
+        fin: true,
+        opcode: WsOpcode::Binary,
+        payload: vec![7; 70000],
+    };
+    let mut out = Vec::new();
+    write_frame(&mut out, &big, None).await.unwrap();
+    let mut stream = &out[..];
+    assert_eq!(read_frame(&mut stream, 100000, false).await.unwrap(), big);
+    let mut stream = &out[..];
+    assert!(read_frame(&mut stream, 1024, false).await.is_err());
+    let mut stream = &out[..];
+    assert!(read_frame(&mut stream, 100000, true).await.is_err());
+}
+
+#[test]
+fn websocket_fragment_tests() {
+    let mut assembler = WsAssembler::new(1024);
+    let first = WsFrame {
+        fin: false,
+        opcode: WsOpcode::Text,
+        payload: b"Hel".to_vec(),
+    };
+    let ping = WsFrame::from_message(&WsMessage::Ping(Arc::new([1])));
+    let last = WsFrame {
+        fin: true,
+        opcode: WsOpcode::Continuation,
+        payload: b"lo".to_vec(),
+    };
+    assert_eq!(assembler.push(first).unwrap(), None);
+    assert_eq!(
+        assembler.push(ping).unwrap(),
+        Some(WsMessage::Ping(Arc::new([1])))
+    );
+    assert_eq!(
+        assembler.push(last).unwrap(),
+        Some(WsMessage::Text("Hello".into()))
+    );
+}